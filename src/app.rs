@@ -2,16 +2,45 @@ use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
 
+use crate::types::{BoardSize, Color, Palette, DEFAULT_COLORS, MAX_NAME_LEN, MIN_COLORS};
+use strum::EnumCount;
+
+#[cfg(not(feature = "ssr"))]
+mod board;
+#[cfg(not(feature = "ssr"))]
+mod connection;
 #[cfg(not(feature = "ssr"))]
 mod game;
 #[cfg(not(feature = "ssr"))]
+mod leaderboard;
+#[cfg(not(feature = "ssr"))]
+mod practice;
+#[cfg(not(feature = "ssr"))]
+mod replay;
+mod settings;
+#[cfg(not(feature = "ssr"))]
+mod spectate;
+#[cfg(not(feature = "ssr"))]
 use game::Game;
+#[cfg(not(feature = "ssr"))]
+use leaderboard::Leaderboard;
+#[cfg(not(feature = "ssr"))]
+use practice::Practice;
+#[cfg(not(feature = "ssr"))]
+use replay::ReplayViewer;
+#[cfg(not(feature = "ssr"))]
+use spectate::Spectate;
 
 #[component]
 pub fn App() -> impl IntoView {
     // Provides context that manages stylesheets, titles, meta tags, etc.
     provide_meta_context();
 
+    // every page draws tiles the way the player picked on the home page
+    let tile_style = create_rw_signal(settings::TileStyle::default());
+    create_effect(move |_| tile_style.set(settings::load_tile_style()));
+    provide_context(tile_style);
+
     view! {
         // injects a stylesheet into the document <head>
         // id=leptos means cargo-leptos will hot-reload this stylesheet
@@ -22,10 +51,14 @@ pub fn App() -> impl IntoView {
 
         // content for this welcome page
         <Router>
-            <main>
+            <main class=move || tile_style.get().class()>
                 <Routes>
                     <Route path="" view=|| view! { <HomePage/> }/>
                     <Route path="/game" view=|| view! { <Game/> }/>
+                    <Route path="/practice" view=|| view! { <Practice/> }/>
+                    <Route path="/spectate/:id" view=|| view! { <Spectate/> }/>
+                    <Route path="/replay/:id" view=|| view! { <ReplayViewer/> }/>
+                    <Route path="/leaderboard" view=|| view! { <Leaderboard/> }/>
                 </Routes>
             </main>
         </Router>
@@ -36,19 +69,324 @@ pub fn App() -> impl IntoView {
 #[component]
 fn Game() -> impl IntoView {
     let (dimensions, _) = create_signal((0, 0));
-    game_view(dimensions, None::<()>, None::<()>, None::<()>, None::<()>)
+    game_view(
+        dimensions, None::<()>, None::<()>, "Opponent", None::<()>, None::<()>, None::<()>,
+        None::<()>, None::<()>,
+    )
+}
+
+#[cfg(feature = "ssr")]
+#[component]
+fn Practice() -> impl IntoView {
+    let (dimensions, _) = create_signal((0, 0));
+    practice_view(dimensions, None::<()>, None::<()>, None::<()>, None::<()>)
+}
+
+#[cfg(feature = "ssr")]
+#[component]
+fn Spectate() -> impl IntoView {
+    let (dimensions, _) = create_signal((0, 0));
+    spectate_view(
+        dimensions,
+        None::<()>,
+        ["Player 1", "Player 2"],
+        [None::<()>, None::<()>],
+        None::<()>,
+        None::<()>,
+    )
+}
+
+#[cfg(feature = "ssr")]
+#[component]
+fn ReplayViewer() -> impl IntoView {
+    let (dimensions, _) = create_signal((0, 0));
+    spectate_view(
+        dimensions,
+        None::<()>,
+        ["Player 1", "Player 2"],
+        [None::<()>, None::<()>],
+        None::<()>,
+        None::<()>,
+    )
+}
+
+#[cfg(feature = "ssr")]
+#[component]
+fn Leaderboard() -> impl IntoView {
+    leaderboard_view(None::<()>, None::<()>, None::<()>)
 }
 
 /// Renders the home page of your application.
 #[component]
 fn HomePage() -> impl IntoView {
+    let (name, set_name) = create_signal(String::new());
+    // effects only run in the browser, where the saved name is
+    create_effect(move |_| set_name(settings::load_name().unwrap_or_default()));
+
     // using Form is a workaround for a redirecting button
     view! {
         <div class="home">
             <h1>"Rubik's Race"</h1>
+            <input
+                class="name"
+                type="text"
+                placeholder="Your name"
+                maxlength=MAX_NAME_LEN
+                prop:value=name
+                on:change=move |ev| settings::save_name(&event_target_value(&ev))
+            />
+            <DisplaySettings/>
             <Form method="GET" action="/game">
                 <button class="button">"Play"</button>
             </Form>
+            <Form method="GET" action="/game" class="match-options">
+                <input type="hidden" name="create" value="true"/>
+                <MatchOptionsFields/>
+                <button class="button">"Create room"</button>
+            </Form>
+            <Form method="GET" action="/game" class="match-options">
+                <select name="bot">
+                    <option value="easy">"Easy"</option>
+                    <option value="medium" selected>"Medium"</option>
+                    <option value="hard">"Hard"</option>
+                </select>
+                <MatchOptionsFields/>
+                <button class="button">"Play vs computer"</button>
+            </Form>
+            <Form method="GET" action="/practice" class="match-options">
+                <BoardFields/>
+                <button class="button">"Practice"</button>
+            </Form>
+            <Form method="GET" action="/game" class="join-room">
+                <input type="text" name="room" placeholder="Room code" maxlength="5" required/>
+                <button class="button">"Join room"</button>
+            </Form>
+            <a class="button" href="/leaderboard">"Leaderboard"</a>
+        </div>
+    }
+}
+
+/// Settings for how the game is shown and played, saved as soon as they change.
+#[component]
+fn DisplaySettings() -> impl IntoView {
+    let (show_cursor, set_show_cursor) = create_signal(false);
+    create_effect(move |_| set_show_cursor(settings::load_cursor()));
+    let tile_style = use_context::<RwSignal<settings::TileStyle>>().expect("no tile style");
+    let save_tile_style = move || settings::save_tile_style(tile_style.get_untracked());
+
+    view! {
+        <div class="settings">
+            <label title="Arrow keys or WASD move the cursor, and enter clicks">
+                <input
+                    type="checkbox"
+                    prop:checked=show_cursor
+                    on:change=move |ev| settings::save_cursor(event_target_checked(&ev))
+                />
+                "Keyboard cursor"
+            </label>
+            <label title="Marks each color with its own symbol">
+                <input
+                    type="checkbox"
+                    prop:checked=move || tile_style.get().symbols
+                    on:change=move |ev| {
+                        tile_style.update(|style| style.symbols = event_target_checked(&ev));
+                        save_tile_style();
+                    }
+                />
+                "Tile symbols"
+            </label>
+            <select
+                prop:value=move || tile_style.get().color_scheme.to_string()
+                on:change=move |ev| {
+                    let color_scheme = event_target_value(&ev).parse().unwrap_or_default();
+                    tile_style.update(|style| style.color_scheme = color_scheme);
+                    save_tile_style();
+                }
+            >
+                <option value=settings::ColorScheme::Standard.to_string()>"Standard colors"</option>
+                <option value=settings::ColorScheme::HighContrast.to_string()>
+                    "High contrast colors"
+                </option>
+            </select>
+        </div>
+    }
+}
+
+/// Inputs for the options of a game, shared by the forms that set one up.
+#[component]
+fn MatchOptionsFields() -> impl IntoView {
+    view! {
+        <BoardFields/>
+        <select name="best_of">
+            <option value="1" selected>"Single game"</option>
+            <option value="3">"Best of 3"</option>
+            <option value="5">"Best of 5"</option>
+        </select>
+        <label title="Both players start from the same board">
+            <input type="checkbox" name="fair" value="true"/>
+            "Same board"
+        </label>
+        <select name="time_limit" title="When time is up, whoever is closest to the target wins">
+            <option value="" selected>"No time limit"</option>
+            <option value="60">"1 minute"</option>
+            <option value="120">"2 minutes"</option>
+            <option value="300">"5 minutes"</option>
+        </select>
+    }
+}
+
+/// Picks the size of the board and the target, passed on as `size`, and the colors of the
+/// tiles, passed on as `colors` or `tiles`.
+///
+/// Color counts that would need more tiles of a color than a board of the picked size allows
+/// can't be chosen, since the server turns them down.
+#[component]
+fn BoardFields() -> impl IntoView {
+    let (size, set_size) = create_signal(BoardSize::default());
+    let (colors, set_colors) = create_signal(DEFAULT_COLORS);
+    let fits = move |colors: usize| Palette::even(colors, size()).is_some();
+    let pick_size = move |ev| {
+        set_size(event_target_value(&ev).parse().unwrap_or_default());
+        if !fits(colors.get_untracked()) {
+            set_colors(DEFAULT_COLORS);
+        }
+    };
+
+    let size_option = |size: BoardSize, label: &'static str| {
+        view! {
+            <option value=size.to_string() selected=size == BoardSize::default()>
+                {format!("{label} ({n}x{n})", n = size.board)}
+            </option>
+        }
+    };
+    view! {
+        <select name="size" on:change=pick_size>
+            {size_option(BoardSize::SMALL, "Small")}
+            {size_option(BoardSize::NORMAL, "Normal")}
+            {size_option(BoardSize::LARGE, "Large")}
+        </select>
+        <select
+            name="colors"
+            prop:value=move || colors().to_string()
+            on:change=move |ev| set_colors(event_target_value(&ev).parse().unwrap_or(DEFAULT_COLORS))
+        >
+            {(MIN_COLORS..=Color::COUNT)
+                .map(|count| view! {
+                    <option value=count selected=count == DEFAULT_COLORS disabled=move || !fits(count)>
+                        {format!("{count} colors")}
+                    </option>
+                })
+                .collect_view()}
+        </select>
+        <input type="text" name="tiles" placeholder="Tiles per color, e.g. 4,4,4,3" pattern="[0-9]+(,[0-9]+)*"/>
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn game_view(
+    dimensions: ReadSignal<(i32, i32)>,
+    target_view: impl IntoView,
+    board_view: impl IntoView,
+    opponent_label: impl IntoView,
+    opponent_board_view: impl IntoView,
+    state_view: impl IntoView,
+    info_view: impl IntoView,
+    progress_view: impl IntoView,
+    spectate_link: impl IntoView,
+) -> impl IntoView {
+    view! {
+        <div class="background" style={move || format!("--screen-x: {x}; --screen-y: {y}", x = dimensions.get().0, y = dimensions.get().1)}>
+            <p class="target-label">"Target"</p>
+            <div class="target">
+                {target_view}
+            </div>
+            <div class="board">
+                {board_view}
+            </div>
+            <p class="opponent-label">{opponent_label}</p>
+            <div class="opponent-board">
+                {opponent_board_view}
+            </div>
+            {info_view}
+            {progress_view}
+            <p class="spectate-link">{spectate_link}</p>
+            {state_view}
+        </div>
+    }
+}
+
+/// Single player variant of [`game_view`], with the clock in place of the opponent's board
+fn practice_view(
+    dimensions: ReadSignal<(i32, i32)>,
+    target_view: impl IntoView,
+    board_view: impl IntoView,
+    stats_view: impl IntoView,
+    state_view: impl IntoView,
+) -> impl IntoView {
+    view! {
+        <div class="background" style={move || format!("--screen-x: {x}; --screen-y: {y}", x = dimensions.get().0, y = dimensions.get().1)}>
+            <p class="target-label">"Target"</p>
+            <div class="target">
+                {target_view}
+            </div>
+            <div class="board">
+                {board_view}
+            </div>
+            <div class="practice-stats">
+                {stats_view}
+            </div>
+            {state_view}
+        </div>
+    }
+}
+
+/// Read-only variant of [`game_view`] with both players' boards side by side, also used for
+/// replays
+fn spectate_view(
+    dimensions: ReadSignal<(i32, i32)>,
+    target_view: impl IntoView,
+    player_labels: [impl IntoView; 2],
+    board_views: [impl IntoView; 2],
+    state_view: impl IntoView,
+    controls_view: impl IntoView,
+) -> impl IntoView {
+    let [left_label, right_label] = player_labels;
+    let [left_board_view, right_board_view] = board_views;
+
+    view! {
+        <div class="spectate-background" style={move || format!("--screen-x: {x}; --screen-y: {y}", x = dimensions.get().0, y = dimensions.get().1)}>
+            <p class="target-label">"Target"</p>
+            <div class="target">
+                {target_view}
+            </div>
+            <p class="player-label left">{left_label}</p>
+            <div class="spectate-board left">
+                {left_board_view}
+            </div>
+            <p class="player-label right">{right_label}</p>
+            <div class="spectate-board right">
+                {right_board_view}
+            </div>
+            {controls_view}
+            {state_view}
+        </div>
+    }
+}
+
+fn leaderboard_view(
+    top_players_view: impl IntoView,
+    recent_matches_view: impl IntoView,
+    state_view: impl IntoView,
+) -> impl IntoView {
+    view! {
+        <div class="leaderboard">
+            <h1>"Leaderboard"</h1>
+            {state_view}
+            <h2>"Top players"</h2>
+            {top_players_view}
+            <h2>"Recent matches"</h2>
+            {recent_matches_view}
+            <a class="button" href="/">"Back to home"</a>
         </div>
     }
 }
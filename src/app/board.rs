@@ -0,0 +1,308 @@
+#![cfg(not(feature = "ssr"))]
+
+use std::cmp::Ordering;
+
+use leptos::*;
+
+use crate::types::{BoardInner, BoardTiles, Color, Target};
+
+pub(super) fn make_target_view(target: ReadSignal<Option<Target>>) -> impl IntoView {
+    move || {
+        target.get()
+            .map(|target| {
+                let size = target.len();
+                target.into_iter().enumerate().flat_map(move |(i, row)| {
+                    row.into_iter()
+                        .enumerate()
+                        .map(move |(j, color)| view! {
+                            <div class={format!("tile {color}", color = color_string(color))} style={format!("--row: {i}; --col: {j}; --size: {size};")} />
+                        })
+                })
+            })
+            .into_iter()
+            .flatten()
+            .collect_view()
+    }
+}
+
+fn board_iter(
+    board: ReadSignal<Option<Board>>,
+) -> impl Iterator<Item = (usize, impl Fn() -> TileView + Copy)> {
+    let tile_count = board.with(|board| board.as_ref().map_or(0, |board| board.locations.len()));
+
+    (0..tile_count).map(move |idx| {
+        (idx, move || {
+            board.with(move |board| {
+                let board = board.as_ref().unwrap();
+                let pos = board.locations[idx];
+                let tile = board.inner.tiles[pos.0][pos.1].unwrap();
+                TileView {
+                    pos,
+                    tile,
+                    size: board.inner.tiles.len(),
+                }
+            })
+        })
+    })
+}
+
+pub(super) fn make_board_view(
+    board: ReadSignal<Option<Board>>,
+    handle_click: impl Fn(usize) + 'static + Copy,
+    drag: impl Fn() -> Option<Drag> + 'static + Copy,
+) -> impl IntoView {
+    view! {
+        <For
+            each=move || board_iter(board)
+            key=|&(idx, _)| idx
+            view=move |(idx, data)| {
+                let pos = move || data().pos;
+                let color = move || data().tile.color;
+                let i = move || pos().0;
+                let j = move || pos().1;
+                let size = move || data().size;
+                // dragged tiles follow the finger, without easing
+                let offset = move || {
+                    let drag = drag()?;
+                    let pos = pos();
+                    board.with(|board| {
+                        board.as_ref()?.slides_with(drag.from, pos).then_some(drag.offset)
+                    })
+                };
+                let class = move || {
+                    let dragging = if offset().is_some() { " dragging" } else { "" };
+                    format!("tile {color}{dragging}", color = color_string(color()))
+                };
+                let style = move || {
+                    let offset = offset()
+                        .map(|(x, y)| format!(" --drag-x: {x}; --drag-y: {y};"))
+                        .unwrap_or_default();
+                    format!("--row: {i}; --col: {j}; --size: {size};{offset}", i = i(), j = j(), size = size())
+                };
+
+                view! {
+                    <div class=class style=style on:click={move |_| handle_click(idx)} />
+                }
+            }
+        />
+    }
+}
+
+pub(super) fn color_string(color: Color) -> &'static str {
+    match color {
+        Color::White => "white",
+        Color::Yellow => "yellow",
+        Color::Orange => "orange",
+        Color::Red => "red",
+        Color::Green => "green",
+        Color::Blue => "blue",
+        Color::Purple => "purple",
+        Color::Pink => "pink",
+    }
+}
+
+pub(super) struct Board {
+    /// Where each tile is, indexed by the order tiles had when the board was created
+    pub(super) locations: Vec<(usize, usize)>,
+    inner: BoardInner<Tile>,
+}
+
+impl Board {
+    pub(super) fn new(inner: BoardInner) -> Self {
+        let size = inner.tiles.len();
+        let colors = inner.tiles.into_iter().enumerate().flat_map(|(i, row)| {
+            row.into_iter()
+                .enumerate()
+                .filter_map(move |(j, tile)| tile.map(|tile| (i, j, tile)))
+        });
+        let mut locations = Vec::with_capacity(size * size - 1);
+        let mut tiles: BoardTiles<Tile> = vec![vec![None; size]; size];
+
+        for (idx, (i, j, color)) in colors.enumerate() {
+            tiles[i][j] = Some(Tile { idx, color });
+            locations.push((i, j));
+        }
+
+        Board {
+            locations,
+            inner: BoardInner {
+                tiles,
+                hole: inner.hole,
+            },
+        }
+    }
+
+    pub(super) fn matches_target(&self, target: &Target) -> bool {
+        self.inner.matches_target(target)
+    }
+
+    pub(super) fn matching_tiles(&self, target: &Target) -> usize {
+        self.inner.matching_tiles(target)
+    }
+
+    /// Tiles per side of the board
+    pub(super) fn size(&self) -> usize {
+        self.inner.tiles.len()
+    }
+
+    pub(super) fn hole(&self) -> (usize, usize) {
+        self.inner.hole
+    }
+
+    /// The position to click to slide tiles into the hole in `direction`, either the next tile
+    /// or, with `whole_line`, the one at the edge so that every tile up to the hole moves.
+    pub(super) fn slide_from(
+        &self,
+        direction: Direction,
+        whole_line: bool,
+    ) -> Option<(usize, usize)> {
+        let (i, j) = self.hole();
+        let last = self.size() - 1;
+        // the first index runs across the screen, and the second down it
+        let pos = match (direction, whole_line) {
+            (Direction::Up, false) => (i, j + 1),
+            (Direction::Up, true) => (i, last),
+            (Direction::Down, false) => (i, j.checked_sub(1)?),
+            (Direction::Down, true) => (i, 0),
+            (Direction::Left, false) => (i + 1, j),
+            (Direction::Left, true) => (last, j),
+            (Direction::Right, false) => (i.checked_sub(1)?, j),
+            (Direction::Right, true) => (0, j),
+        };
+        (pos != self.hole() && pos.0 <= last && pos.1 <= last).then_some(pos)
+    }
+
+    /// Which way the tile at `pos` slides when clicked, if it is in line with the hole.
+    pub(super) fn direction_to_hole(&self, pos: (usize, usize)) -> Option<Direction> {
+        let (i, j) = self.hole();
+        match (pos.0.cmp(&i), pos.1.cmp(&j)) {
+            (Ordering::Greater, Ordering::Equal) => Some(Direction::Left),
+            (Ordering::Less, Ordering::Equal) => Some(Direction::Right),
+            (Ordering::Equal, Ordering::Greater) => Some(Direction::Up),
+            (Ordering::Equal, Ordering::Less) => Some(Direction::Down),
+            _ => None,
+        }
+    }
+
+    /// Whether clicking `from` moves the tile at `pos`.
+    pub(super) fn slides_with(&self, from: (usize, usize), pos: (usize, usize)) -> bool {
+        let hole = self.hole();
+        let between = |a: usize, b: usize, x: usize| a.min(b) <= x && x <= a.max(b);
+        self.direction_to_hole(from).is_some()
+            && pos != hole
+            && between(from.0, hole.0, pos.0)
+            && between(from.1, hole.1, pos.1)
+    }
+
+    pub(super) fn click_pos(&mut self, pos: (usize, usize)) -> bool {
+        use crate::utils::slide;
+
+        let Self {
+            locations,
+            inner: BoardInner { tiles, hole },
+        } = self;
+        let update = |old: (usize, usize), new: (usize, usize)| {
+            locations[tiles[old.0][old.1].unwrap().idx] = new;
+            tiles[new.0][new.1] = tiles[old.0][old.1];
+        };
+
+        if !slide(pos, *hole, update) {
+            return false;
+        }
+
+        // the clicked tile moved away, don't leave a copy of it behind
+        tiles[pos.0][pos.1] = None;
+        *hole = pos;
+        true
+    }
+}
+
+/// Which way tiles slide, or the cursor moves, when playing with the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    /// Reads the arrow keys and WASD, where shift gives uppercase letters.
+    pub(super) fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "ArrowUp" | "w" | "W" => Some(Direction::Up),
+            "ArrowDown" | "s" | "S" => Some(Direction::Down),
+            "ArrowLeft" | "a" | "A" => Some(Direction::Left),
+            "ArrowRight" | "d" | "D" => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    /// The way a finger moved by `(x, y)` tiles, if it moved at least `threshold` of a tile.
+    pub(super) fn of_swipe((x, y): (f64, f64), threshold: f64) -> Option<Self> {
+        if x.abs().max(y.abs()) < threshold {
+            return None;
+        }
+        Some(if x.abs() > y.abs() {
+            if x > 0.0 {
+                Direction::Right
+            } else {
+                Direction::Left
+            }
+        } else if y > 0.0 {
+            Direction::Down
+        } else {
+            Direction::Up
+        })
+    }
+
+    /// How far tiles sliding this way follow a finger that moved by `(x, y)` tiles, which is
+    /// at most one tile.
+    pub(super) fn offset(self, (x, y): (f64, f64)) -> (f64, f64) {
+        match self {
+            Direction::Up => (0.0, y.clamp(-1.0, 0.0)),
+            Direction::Down => (0.0, y.clamp(0.0, 1.0)),
+            Direction::Left => (x.clamp(-1.0, 0.0), 0.0),
+            Direction::Right => (x.clamp(0.0, 1.0), 0.0),
+        }
+    }
+
+    /// Moves `pos` one step, stopping at the edge of a board with `size` tiles per side.
+    pub(super) fn step(self, (i, j): (usize, usize), size: usize) -> (usize, usize) {
+        match self {
+            Direction::Up => (i, j.saturating_sub(1)),
+            Direction::Down => (i, (j + 1).min(size - 1)),
+            Direction::Left => (i.saturating_sub(1), j),
+            Direction::Right => ((i + 1).min(size - 1), j),
+        }
+    }
+}
+
+/// Tiles being dragged towards the hole, before the finger is lifted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Drag {
+    /// The tile the drag started on, which moves along with every tile up to the hole
+    pub(super) from: (usize, usize),
+    /// How far the tiles have moved across and down, in tiles
+    pub(super) offset: (f64, f64),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TileView {
+    pos: (usize, usize),
+    tile: Tile,
+    /// Tiles per side of the board
+    size: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tile {
+    idx: usize,
+    color: Color,
+}
+
+impl From<Tile> for Color {
+    fn from(value: Tile) -> Self {
+        value.color
+    }
+}
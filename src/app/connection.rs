@@ -0,0 +1,106 @@
+#![cfg(not(feature = "ssr"))]
+
+use leptos::*;
+
+use super::settings::load_name;
+use crate::types::{Capabilities, ClientMessage, Hello, ServerMessage, PROTOCOL_VERSION};
+use core::time::Duration;
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use gloo_net::websocket::{futures::WebSocket, Message};
+use tokio::{
+    select,
+    sync::{broadcast, mpsc},
+};
+
+pub(super) type Void = std::convert::Infallible;
+
+/// Why a websocket connection ended
+pub(super) enum ConnectionEnd {
+    Shutdown,
+    Lost,
+    /// The server speaks another version of the protocol, so the page needs a refresh
+    Outdated,
+}
+
+pub(super) async fn run_connection(
+    ws: WebSocket,
+    msg_rx: &mut mpsc::UnboundedReceiver<ClientMessage>,
+    shutdown_rx: &mut broadcast::Receiver<Void>,
+    handle_server_message: impl Fn(ServerMessage),
+) -> ConnectionEnd {
+    let (mut tx, mut rx) = futures::StreamExt::split(ws);
+    let mut ping_interval = wasmtimer::tokio::interval(Duration::from_secs(50));
+
+    async fn send_msg(
+        msg: ClientMessage,
+        tx: &mut SplitSink<WebSocket, Message>,
+    ) -> Result<(), gloo_net::websocket::WebSocketError> {
+        let msg = Message::Bytes(bincode::serialize(&msg).expect("failed to serialize"));
+        tx.send(msg).await
+    }
+
+    let hello =
+        bincode::serialize(&Hello::current(Capabilities::ALL)).expect("failed to serialize");
+    if let Err(e) = tx.send(Message::Bytes(hello)).await {
+        log!("Failed to send message: {e}");
+        return ConnectionEnd::Lost;
+    }
+    let server_hello = match rx.next().await {
+        Some(Ok(Message::Bytes(msg))) => bincode::deserialize::<Hello>(&msg).ok(),
+        _ => return ConnectionEnd::Lost,
+    };
+    // the server answers with our own version if it can talk to us
+    match server_hello {
+        Some(Hello { version, .. }) if version == PROTOCOL_VERSION => {}
+        hello => {
+            log!("Server turned down our protocol version: {hello:?}");
+            return ConnectionEnd::Outdated;
+        }
+    }
+
+    if let Err(e) = send_msg(ClientMessage::Join { name: load_name() }, &mut tx).await {
+        log!("Failed to send message: {e}");
+        return ConnectionEnd::Lost;
+    }
+
+    loop {
+        select! {
+            msg = rx.next() => {
+                let msg = match msg {
+                    Some(Ok(Message::Bytes(msg))) => msg,
+                    Some(Ok(msg)) => {
+                        log!("Unexpected message: {msg:?}");
+                        return ConnectionEnd::Lost;
+                    }
+                    Some(Err(e)) => {
+                        log!("Receive error: {e}");
+                        return ConnectionEnd::Lost;
+                    }
+                    None => return ConnectionEnd::Lost,
+                };
+                // the handshake should rule this out, but a refresh beats a panic
+                let Ok(msg) = bincode::deserialize::<ServerMessage>(&msg) else {
+                    log!("Failed to deserialize message");
+                    return ConnectionEnd::Outdated;
+                };
+                handle_server_message(msg);
+            }
+            msg = msg_rx.recv() => {
+                let Some(msg) = msg else { return ConnectionEnd::Shutdown; };
+                if let Err(e) = send_msg(msg, &mut tx).await {
+                    log!("Failed to send message: {e}");
+                    return ConnectionEnd::Lost;
+                }
+            }
+            _ = ping_interval.tick() => {
+                if let Err(e) = send_msg(ClientMessage::Ping, &mut tx).await {
+                    log!("Failed to send message: {e}");
+                    return ConnectionEnd::Lost;
+                }
+            }
+            _ = shutdown_rx.recv() => {
+                return ConnectionEnd::Shutdown;
+            }
+        }
+    }
+}
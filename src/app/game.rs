@@ -1,38 +1,69 @@
 #![cfg(not(feature = "ssr"))]
 
 use leptos::*;
+use leptos_router::{use_query_map, ParamsMap};
 
-use crate::types::{BoardInner, BoardTiles, ClientMessage, Color, ServerMessage, Target};
-use futures::{SinkExt, StreamExt};
-use gloo_net::websocket::{futures::WebSocket, Message};
+use super::{
+    board::{make_board_view, make_target_view, Board, Direction, Drag},
+    connection::{run_connection, ConnectionEnd, Void},
+    game_view, settings,
+};
+use crate::types::{
+    parse_tile_counts, BoardSize, ClientMessage, GameId, GameStart, MoveError, Outcome,
+    RatingChange, Series, ServerMessage, Target,
+};
+use core::time::Duration;
+use gloo_net::websocket::futures::WebSocket;
 use tokio::{
     select,
     sync::{broadcast, mpsc},
 };
 use wasm_bindgen::{closure::Closure, JsCast};
-
-type Void = std::convert::Infallible;
+use wasmtimer::std::Instant;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum State {
     WaitingForOpponent,
     Playing,
     WaitGameEnd, // target is matched, but server hasn't sent game end yet
-    GameEnd { is_win: bool },
+    GameEnd {
+        outcome: Outcome,
+        time_up: bool,
+        rating: Option<RatingChange>,
+    },
+    Reconnecting,
     OpponentLeft,
+    RoomNotFound,
+    SessionExpired,
     ConnectionError,
+    /// The server runs a newer version
+    Outdated,
+}
+
+/// Whether another series can be played against the same opponent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rematch {
+    Unavailable,
+    Available,
+    Requested,
+    OpponentRequested,
 }
 
 impl State {
     fn is_end(&self) -> bool {
         matches!(
             self,
-            State::GameEnd { .. } | State::OpponentLeft | State::ConnectionError
+            State::GameEnd { .. }
+                | State::OpponentLeft
+                | State::RoomNotFound
+                | State::SessionExpired
+                | State::ConnectionError
+                | State::Outdated
         )
     }
 }
 
-fn window_dimensions() -> (i32, i32) {
+pub(super) fn window_dimensions() -> (i32, i32) {
     let window = web_sys::window().expect("should have a window");
     let document = window.document().expect("no document");
     let root = document.document_element().expect("no root");
@@ -40,11 +71,51 @@ fn window_dimensions() -> (i32, i32) {
     (root.client_width(), root.client_height())
 }
 
+/// How long to wait between attempts to reconnect to a game
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+/// The server holds the game for 30 seconds, so there's no use trying for longer
+const MAX_RECONNECT_ATTEMPTS: usize = 15;
+
+/// How far a finger has to move, in tiles, for a touch to be a swipe rather than a tap
+const SWIPE_THRESHOLD: f64 = 0.3;
+
+/// Where a touch on the board started
+#[derive(Debug, Clone, Copy)]
+struct TouchStart {
+    x: i32,
+    y: i32,
+    /// Width of a tile on screen, since the board is scaled to fit the window
+    tile_px: f64,
+    pos: (usize, usize),
+}
+
+/// The board size and colors picked on the home page, as query parameters for the server.
+pub(super) fn board_params(query: &ParamsMap) -> String {
+    // parsing checks the values, and doubles as url encoding
+    let size = query
+        .get("size")
+        .and_then(|size| size.parse::<BoardSize>().ok())
+        .map(|size| format!("&size={size}"))
+        .unwrap_or_default();
+    let colors = query
+        .get("colors")
+        .and_then(|colors| colors.parse::<usize>().ok())
+        .map(|colors| format!("&colors={colors}"))
+        .unwrap_or_default();
+    let tiles = query
+        .get("tiles")
+        .and_then(|tiles| parse_tile_counts(tiles))
+        .map(|counts| {
+            let counts: Vec<_> = counts.iter().map(usize::to_string).collect();
+            format!("&tiles={}", counts.join(","))
+        })
+        .unwrap_or_default();
+    format!("{size}{colors}{tiles}")
+}
+
 #[component]
 pub(super) fn Game() -> impl IntoView {
-    let (shutdown_tx, shutdown_rx) = broadcast::channel::<Void>(1);
-    let mut send_shutdown = shutdown_rx;
-    let mut recv_shutdown = shutdown_tx.subscribe();
+    let (shutdown_tx, mut shutdown_rx) = broadcast::channel::<Void>(1);
 
     let shutdown_tx = store_value(Some(shutdown_tx));
     let do_shutdown = move || shutdown_tx.set_value(None);
@@ -61,10 +132,34 @@ pub(super) fn Game() -> impl IntoView {
     let host = window.location().host().expect("failed to get location");
 
     let (state, set_state) = create_signal(State::WaitingForOpponent);
-    let (target, set_target) = create_signal(None::<[[Color; 3]; 3]>);
+    let (room_code, set_room_code) = create_signal(None::<String>);
+    let (game_id, set_game_id) = create_signal(None::<GameId>);
+    let (opponent_connected, set_opponent_connected) = create_signal(true);
+    let (opponent_name, set_opponent_name) = create_signal(None::<String>);
+    let (opponent_is_bot, set_opponent_is_bot) = create_signal(false);
+    let (series, set_series) = create_signal(Series::new(1));
+    let (rematch, set_rematch) = create_signal(Rematch::Unavailable);
+    let (target, set_target) = create_signal(None::<Target>);
     let (board, set_board) = create_signal(None::<Board>);
     let (opponent_board, set_opponent_board) = create_signal(None::<Board>);
+    // moves made this round, own first
+    let (moves, set_moves) = create_signal([0u32; 2]);
+    // where enter clicks, if the player turned the keyboard cursor on
+    let show_cursor = settings::load_cursor();
+    let (cursor, set_cursor) = create_signal(None::<(usize, usize)>);
+    // the last rejected click this round, and how many more forfeit it
+    let (rejection, set_rejection) = create_signal(None::<(MoveError, Option<u32>)>);
     let (dimensions, set_dimensions) = create_signal(window_dimensions());
+    let session = store_value(None::<String>);
+    let (time_limit, set_time_limit) = create_signal(None::<Duration>);
+    let (elapsed, set_elapsed) = create_signal(Duration::ZERO);
+    // the server's round time as of the last message, and when that message arrived
+    let clock_base = store_value(None::<(Duration, Instant)>);
+    let sync_clock = move |elapsed_ms: u64| {
+        let elapsed = Duration::from_millis(elapsed_ms);
+        clock_base.set_value(Some((elapsed, Instant::now())));
+        set_elapsed(elapsed);
+    };
 
     let resize_cb = Closure::<dyn Fn()>::new(move || {
         set_dimensions(window_dimensions());
@@ -77,133 +172,324 @@ pub(super) fn Game() -> impl IntoView {
     let reload = move |_| {
         _ = window().location().reload();
     };
+    let go_home = move |_| {
+        _ = window().location().set_href("/");
+    };
+
+    // the clock only runs while playing, the server decides when time is up
+    if let Ok(timer) = set_interval_with_handle(
+        move || {
+            if state.get_untracked() == State::Playing {
+                if let Some((elapsed, at)) = clock_base.get_value() {
+                    set_elapsed(elapsed + at.elapsed());
+                }
+            }
+        },
+        Duration::from_millis(100),
+    ) {
+        on_cleanup(move || timer.clear());
+    }
+
+    let (query, is_private) = use_query_map().with_untracked(|query| {
+        // options for a game set up by this player, passed on to the server
+        // numbers are left out when empty, since the server can't parse those
+        let number = |key: &str| {
+            let digits: String = query
+                .get(key)
+                .map(|value| value.chars().filter(char::is_ascii_digit).collect())
+                .unwrap_or_default();
+            if digits.is_empty() {
+                String::new()
+            } else {
+                format!("&{key}={digits}")
+            }
+        };
+        let fair = query.get("fair").is_some();
+        let board_options = board_params(query);
+        let options = format!(
+            "{}&fair={fair}{}{board_options}",
+            number("best_of"),
+            number("time_limit")
+        );
+
+        if query.get("create").is_some() {
+            (format!("?create_room=true{options}"), true)
+        } else if let Some(code) = query.get("room") {
+            // room codes are alphanumeric, so this doubles as url encoding
+            let code: String = code.chars().filter(char::is_ascii_alphanumeric).collect();
+            set_room_code(Some(code.to_ascii_uppercase()));
+            (format!("?room={code}"), true)
+        } else if let Some(difficulty) = query.get("bot") {
+            let difficulty: String = difficulty
+                .chars()
+                .filter(char::is_ascii_alphabetic)
+                .collect();
+            (format!("?bot={difficulty}{options}"), false)
+        } else {
+            // only public games are rated
+            let key = settings::load_player_key()
+                .map(|key| format!("?key={key}"))
+                .unwrap_or_default();
+            (key, false)
+        }
+    });
 
-    let ws = WebSocket::open(&format!("wss://{host}/connect")).expect("could not connect");
-    let (mut tx, mut rx) = futures::StreamExt::split(ws);
     let (msg_tx, mut msg_rx) = mpsc::unbounded_channel::<ClientMessage>();
 
     // this wrapping is needed since msg_tx is not Copy
     let msg_tx = store_value(msg_tx);
 
-    // websocket send loop
-    spawn_local(async move {
-        use futures::stream::SplitSink;
-
-        log!("Entering send loop");
-
-        let mut ping_interval = wasmtimer::tokio::interval(core::time::Duration::from_secs(50));
-
-        async fn send_msg(
-            msg: ClientMessage,
-            tx: &mut SplitSink<WebSocket, Message>,
-            set_state: WriteSignal<State>,
-            do_shutdown: impl Fn(),
-        ) {
-            let msg = Message::Bytes(bincode::serialize(&msg).expect("failed to serialize"));
-            if let Err(e) = tx.send(msg).await {
-                log!("Failed to send message: {e}");
-                set_state(State::ConnectionError);
-                do_shutdown();
-            }
-        }
+    let request_rematch = move |_| {
+        _ = msg_tx.with_value(|msg_tx| msg_tx.send(ClientMessage::Rematch));
+        set_rematch(Rematch::Requested);
+    };
 
-        loop {
-            select! {
-                msg = msg_rx.recv() => {
-                    let Some(msg) = msg else { break; };
-                    send_msg(msg, &mut tx, set_state, do_shutdown).await;
-                }
-                _ = ping_interval.tick() => {
-                    send_msg(ClientMessage::Ping, &mut tx, set_state, do_shutdown).await;
-                }
-                _ = send_shutdown.recv() => {
-                    break;
-                }
-            }
+    let set_game_state = move |start: GameStart| {
+        set_game_id(Some(start.game_id));
+        set_target(Some(start.target));
+        if show_cursor {
+            set_cursor(Some(start.board.hole));
         }
-        log!("Exiting send loop");
-    });
+        set_board(Some(Board::new(start.board)));
+        set_opponent_board(Some(Board::new(start.opponent_board)));
+        set_moves(start.moves);
+        set_rejection(None);
+        session.set_value(Some(start.session));
+        set_opponent_name(start.opponent_name);
+        set_opponent_is_bot(start.opponent_is_bot);
+        set_series(start.series);
+        set_time_limit(start.time_limit_ms.map(Duration::from_millis));
+        sync_clock(start.elapsed_ms);
+    };
+    let is_series_over = move || series.get_untracked().winner().is_some();
 
     let handle_server_message = move |msg: ServerMessage| {
         match msg {
             ServerMessage::GameStart(start) => {
-                if state.get_untracked() != State::WaitingForOpponent {
-                    log!("Got game start but not waiting for opponent");
+                // later rounds and rematches start after the previous game ended
+                let state = state.get_untracked();
+                if state.is_end() && !matches!(state, State::GameEnd { .. }) {
+                    log!("Got game start after leaving the game");
                     return;
                 }
 
-                set_target(Some(start.target));
-                set_board(Some(Board::new(start.board)));
-                set_opponent_board(Some(Board::new(start.opponent_board)));
+                set_game_state(start);
+                set_rematch(Rematch::Unavailable);
                 set_state(State::Playing);
 
                 // assumption: initial configuration will never contain the target
             }
-            ServerMessage::OpponentLeft => {
-                if !state.get_untracked().is_end() {
+            ServerMessage::OpponentLeft => match state.get_untracked() {
+                State::GameEnd { .. } if is_series_over() => {
+                    set_rematch(Rematch::Unavailable);
+                    do_shutdown();
+                }
+                // leaving between rounds forfeits the rest of the series
+                State::GameEnd { .. } => {
                     set_state(State::OpponentLeft);
                     do_shutdown();
                 }
-            }
-            ServerMessage::OpponentClick { pos } => {
+                state if !state.is_end() => {
+                    set_state(State::OpponentLeft);
+                    do_shutdown();
+                }
+                _ => {}
+            },
+            ServerMessage::OpponentClick { pos, time_ms } => {
                 if state.get_untracked() != State::Playing {
                     log!("Got opponent click but not playing");
                     return;
                 }
+                sync_clock(time_ms);
 
                 set_opponent_board.update(|board| {
                     board.as_mut().expect("playing but no board").click_pos(pos);
                 });
+                set_moves.update(|moves| moves[1] += 1);
             }
-            ServerMessage::GameEnd { is_win } => {
+            ServerMessage::GameEnd {
+                outcome,
+                time_up,
+                rating,
+                series,
+            } => {
                 if matches!(state.get_untracked(), State::Playing | State::WaitGameEnd) {
-                    set_state(State::GameEnd { is_win });
-                    do_shutdown();
+                    set_series(series);
+                    set_state(State::GameEnd {
+                        outcome,
+                        time_up,
+                        rating,
+                    });
+                    if series.winner().is_some() {
+                        set_rematch(Rematch::Available);
+                    }
                 } else {
                     log!("Got game end but not playing");
                 }
             }
+            ServerMessage::RoomCreated { code } => {
+                set_room_code(Some(code));
+            }
+            ServerMessage::RoomNotFound => {
+                set_state(State::RoomNotFound);
+                do_shutdown();
+            }
+            ServerMessage::OpponentDisconnected => {
+                set_opponent_connected(false);
+            }
+            ServerMessage::OpponentReconnected => {
+                set_opponent_connected(true);
+            }
+            ServerMessage::Resync(start) => {
+                // a rejected click is answered with a resync when the server doesn't warn about it
+                if !matches!(
+                    state.get_untracked(),
+                    State::Reconnecting | State::Playing | State::WaitGameEnd
+                ) {
+                    log!("Got resync but not in a game");
+                    return;
+                }
+
+                // a round that ended meanwhile is followed by its game end
+                set_game_state(start);
+                set_rematch(Rematch::Unavailable);
+                set_state(State::Playing);
+            }
+            ServerMessage::SessionExpired => {
+                if !state.get_untracked().is_end() {
+                    set_state(State::SessionExpired);
+                    do_shutdown();
+                }
+            }
+            ServerMessage::Clock { elapsed_ms } => {
+                if state.get_untracked() == State::Playing {
+                    sync_clock(elapsed_ms);
+                }
+            }
+            ServerMessage::RematchRequested => {
+                if rematch.get_untracked() == Rematch::Available {
+                    set_rematch(Rematch::OpponentRequested);
+                }
+            }
+            ServerMessage::MoveRejected {
+                reason,
+                board,
+                strikes_left,
+                ..
+            } => {
+                let state = state.get_untracked();
+                if !matches!(state, State::Playing | State::WaitGameEnd) {
+                    log!("Got move rejection but not playing");
+                    return;
+                }
+                log!("Click was rejected: {reason}");
+
+                set_board(Some(Board::new(board)));
+                // the click was counted when it was made
+                set_moves.update(|moves| moves[0] = moves[0].saturating_sub(1));
+                set_rejection(Some((reason, strikes_left)));
+                // the target was only matched on a board the server never had
+                if state == State::WaitGameEnd {
+                    set_state(State::Playing);
+                }
+            }
+            ServerMessage::SpectateStart(_)
+            | ServerMessage::PlayerClick { .. }
+            | ServerMessage::GameOver { .. }
+            | ServerMessage::GameNotFound
+            | ServerMessage::PracticeStart { .. }
+            | ServerMessage::PracticeEnd { .. } => {
+                log!("Got unexpected message while playing");
+            }
         }
     };
 
-    // websocket receive loop
+    // websocket loop, which reconnects to the game if the connection is lost
     spawn_local(async move {
-        'outer: loop {
-            select! {
-                msg = rx.next() => {
-                    let Some(msg) = msg else { break; };
-                    let msg = 'msg: {
-                        match msg {
-                            Ok(Message::Bytes(msg)) => break 'msg msg,
-                            Ok(msg) => log!("Unexpected message: {msg:?}"),
-                            Err(e) => log!("Receive error: {e}"),
-                        };
-                        set_state(State::ConnectionError);
-                        do_shutdown();
-                        break 'outer;
-                    };
-                    let msg: ServerMessage = bincode::deserialize(&msg).expect("failed to deserialize");
-                    handle_server_message(msg);
+        let mut url = format!("wss://{host}/connect{query}");
+        let mut attempts = 0;
+
+        log!("Entering websocket loop");
+
+        loop {
+            let end = match WebSocket::open(&url) {
+                Ok(ws) => {
+                    run_connection(ws, &mut msg_rx, &mut shutdown_rx, handle_server_message).await
                 }
-                _ = recv_shutdown.recv() => {
+                Err(e) => {
+                    log!("Failed to connect: {e}");
+                    ConnectionEnd::Lost
+                }
+            };
+
+            match end {
+                ConnectionEnd::Lost => {}
+                ConnectionEnd::Shutdown => break,
+                ConnectionEnd::Outdated => {
+                    set_state(State::Outdated);
+                    do_shutdown();
                     break;
                 }
             }
+
+            // only a game in progress can be resumed, which includes the break between rounds
+            let can_resume = match state.get_untracked() {
+                State::Playing | State::WaitGameEnd | State::Reconnecting => true,
+                State::GameEnd { .. } => !is_series_over(),
+                _ => false,
+            };
+            let session = session.get_value();
+            // the server lets go of a finished game, which is no error
+            if !can_resume && matches!(state.get_untracked(), State::GameEnd { .. }) {
+                set_rematch(Rematch::Unavailable);
+                do_shutdown();
+                break;
+            }
+            let Some(session) = session.filter(|_| can_resume) else {
+                set_state(State::ConnectionError);
+                do_shutdown();
+                break;
+            };
+
+            // a resync puts us back in the game, so we start counting again
+            if state.get_untracked() != State::Reconnecting {
+                attempts = 0;
+            }
+            if attempts == MAX_RECONNECT_ATTEMPTS {
+                set_state(State::ConnectionError);
+                do_shutdown();
+                break;
+            }
+            attempts += 1;
+            set_state(State::Reconnecting);
+
+            // unsent clicks would be applied on top of the resynced board
+            while msg_rx.try_recv().is_ok() {}
+
+            select! {
+                _ = wasmtimer::tokio::sleep(RECONNECT_DELAY) => {}
+                _ = shutdown_rx.recv() => break,
+            }
+
+            log!("Reconnecting, attempt {attempts}");
+            url = format!("wss://{host}/connect?session={session}");
         }
+
+        log!("Exiting websocket loop");
     });
 
-    let handle_click = move |idx: usize| {
+    let click_pos = move |pos: (usize, usize)| {
         if state() != State::Playing {
             return;
         }
         set_board.update(|board| {
             let board = board.as_mut().expect("playing but no board");
-            let pos = board.locations[idx];
             let updated = board.click_pos(pos);
             if !updated {
                 return;
             }
             _ = msg_tx.with_value(|msg_tx| msg_tx.send(ClientMessage::Click { pos }));
+            set_moves.update(|moves| moves[0] += 1);
             let is_game_over = target.with(|target| {
                 board.matches_target(target.as_ref().expect("playing but no target"))
             });
@@ -213,206 +499,335 @@ pub(super) fn Game() -> impl IntoView {
             }
         })
     };
+    let handle_click = move |idx: usize| {
+        if let Some(pos) = board.with(|board| board.as_ref().map(|board| board.locations[idx])) {
+            click_pos(pos);
+        }
+    };
 
-    let target_view = move || {
-        target.get()
-            .map(|target| {
-                target.into_iter().enumerate().flat_map(|(i, row)| {
-                    row.into_iter()
-                        .enumerate()
-                        .map(move |(j, color)| view! {
-                            <div class={format!("tile {color}", color = color_string(color))} style={format!("--row: {i}; --col: {j};")} />
-                        })
-                })
-            })
-            .into_iter()
-            .flatten()
-            .collect_view()
+    // arrow keys and WASD slide tiles into the hole, or move the cursor if it is shown
+    // with shift, every tile in line with the hole slides at once
+    let handle_key = move |ev: web_sys::KeyboardEvent| {
+        if ev.ctrl_key() || ev.alt_key() || ev.meta_key() {
+            return;
+        }
+        let key = ev.key();
+        if let Some(direction) = Direction::from_key(&key) {
+            // the arrow keys would scroll the page too
+            ev.prevent_default();
+            match cursor.get_untracked() {
+                Some(pos) if !ev.shift_key() => {
+                    let size = board.with_untracked(|board| board.as_ref().map(Board::size));
+                    if let Some(size) = size {
+                        set_cursor(Some(direction.step(pos, size)));
+                    }
+                }
+                _ => {
+                    let pos = board.with_untracked(|board| {
+                        board.as_ref()?.slide_from(direction, ev.shift_key())
+                    });
+                    if let Some(pos) = pos {
+                        click_pos(pos);
+                    }
+                }
+            }
+        } else if let (Some(pos), "Enter" | " ") = (cursor.get_untracked(), key.as_str()) {
+            ev.prevent_default();
+            click_pos(pos);
+        }
     };
+    let key_cb = Closure::<dyn Fn(web_sys::KeyboardEvent)>::new(handle_key);
+    window.with_value(|window| window.set_onkeydown(Some(key_cb.as_ref().unchecked_ref())));
+    let _key_cb = store_value(key_cb);
+    // the callback is dropped with the page, which can be left without reloading
+    on_cleanup(|| {
+        if let Some(window) = web_sys::window() {
+            window.set_onkeydown(None);
+        }
+    });
 
-    fn board_iter(
-        board: ReadSignal<Option<Board>>,
-    ) -> impl Iterator<Item = (usize, impl Fn() -> TileView + Copy)> {
-        let range = if board.with(|board| board.is_some()) {
-            0..24
-        } else {
-            0..0
+    // swiping slides tiles like the arrow keys do, unless it starts on a tile in line with the
+    // hole and heads for it, which slides that tile and the ones before it along with the finger
+    let touch_start = store_value(None::<TouchStart>);
+    let (drag, set_drag) = create_signal(None::<Drag>);
+    let touch_delta = |start: TouchStart, touch: web_sys::Touch| {
+        (
+            f64::from(touch.client_x() - start.x) / start.tile_px,
+            f64::from(touch.client_y() - start.y) / start.tile_px,
+        )
+    };
+    let handle_touch_start = move |ev: web_sys::TouchEvent| {
+        touch_start.set_value(None);
+        if state.get_untracked() != State::Playing || ev.touches().length() != 1 {
+            return;
+        }
+        let area = ev
+            .current_target()
+            .and_then(|target| target.dyn_into::<web_sys::Element>().ok());
+        let size = board.with_untracked(|board| board.as_ref().map(Board::size));
+        let (Some(touch), Some(area), Some(size)) = (ev.touches().get(0), area, size) else {
+            return;
         };
 
-        range.into_iter().map(move |idx| {
-            (idx, move || {
-                board.with(move |board| {
-                    let board = board.as_ref().unwrap();
-                    let pos = board.locations[idx];
-                    let tile = board.inner.tiles[pos.0][pos.1].unwrap();
-                    TileView { pos, tile }
-                })
-            })
-        })
-    }
-
-    fn make_board_view(
-        board: ReadSignal<Option<Board>>,
-        handle_click: impl Fn(usize) + 'static + Copy,
-    ) -> impl IntoView {
-        view! {
-            <For
-                each=move || board_iter(board)
-                key=|&(idx, _)| idx
-                view=move |(idx, data)| {
-                    let pos = move || data().pos;
-                    let color = move || data().tile.color;
-                    let i = move || pos().0;
-                    let j = move || pos().1;
-
-                    view! {
-                        <div class={move || format!("tile {color}", color = color_string(color()))} style={move || format!("--row: {i}; --col: {j};", i = i(), j = j())} on:click={move |_| handle_click(idx)} />
-                    }
-                }
-            />
+        let rect = area.get_bounding_client_rect();
+        let tile_px = rect.width() / size as f64;
+        // touches just outside the edge count as on it
+        let tile = |offset: f64| ((offset / tile_px) as usize).min(size - 1);
+        let pos = (
+            tile(f64::from(touch.client_x()) - rect.left()),
+            tile(f64::from(touch.client_y()) - rect.top()),
+        );
+        touch_start.set_value(Some(TouchStart {
+            x: touch.client_x(),
+            y: touch.client_y(),
+            tile_px,
+            pos,
+        }));
+    };
+    let handle_touch_move = move |ev: web_sys::TouchEvent| {
+        let (Some(start), Some(touch)) = (touch_start.get_value(), ev.touches().get(0)) else {
+            return;
+        };
+        let direction = board.with_untracked(|board| board.as_ref()?.direction_to_hole(start.pos));
+        set_drag(direction.map(|direction| Drag {
+            from: start.pos,
+            offset: direction.offset(touch_delta(start, touch)),
+        }));
+    };
+    let handle_touch_end = move |ev: web_sys::TouchEvent| {
+        set_drag(None);
+        let (Some(start), Some(touch)) = (touch_start.get_value(), ev.changed_touches().get(0))
+        else {
+            return;
+        };
+        touch_start.set_value(None);
+        // taps are left to the click handler
+        let Some(swipe) = Direction::of_swipe(touch_delta(start, touch), SWIPE_THRESHOLD) else {
+            return;
+        };
+        let pos = board.with_untracked(|board| {
+            let board = board.as_ref()?;
+            if board.direction_to_hole(start.pos) == Some(swipe) {
+                Some(start.pos)
+            } else {
+                board.slide_from(swipe, false)
+            }
+        });
+        if let Some(pos) = pos {
+            click_pos(pos);
         }
-    }
+    };
+    let handle_touch_cancel = move |_| {
+        touch_start.set_value(None);
+        set_drag(None);
+    };
+
+    let target_view = make_target_view(target);
 
-    let board_view = make_board_view(board, handle_click);
-    let opponent_board_view = make_board_view(opponent_board, |_| {});
+    let cursor_view = move || {
+        let (i, j) = cursor.get()?;
+        let size = board.with(|board| board.as_ref().map(Board::size))?;
+        Some(view! {
+            <div class="cursor" style={format!("--row: {i}; --col: {j}; --size: {size};")} />
+        })
+    };
+    let board_view = view! {
+        <div
+            class="touch-area"
+            on:touchstart=handle_touch_start
+            on:touchmove=handle_touch_move
+            on:touchend=handle_touch_end
+            on:touchcancel=handle_touch_cancel
+        >
+            {make_board_view(board, handle_click, drag)}
+            {cursor_view}
+        </div>
+    };
+    let opponent_board_view = make_board_view(opponent_board, |_| {}, || None);
 
     let state_view = move || {
+        let room_code_view = move || {
+            let code = room_code.get()?;
+            (state.get() == State::WaitingForOpponent).then(|| {
+                view! { <span class="room-code">"Room code: " {code}</span> }
+            })
+        };
         let message = match state.get() {
             State::WaitingForOpponent => "Waiting for opponent",
-            State::GameEnd { is_win } => {
-                if is_win {
-                    "You win!"
-                } else {
-                    "You lose!"
+            State::GameEnd {
+                outcome, time_up, ..
+            } => {
+                let series = series.get();
+                match (outcome, time_up, series.best_of, series.winner()) {
+                    (Outcome::Win, true, 1, _) => "Time's up! You were closer, you win!",
+                    (Outcome::Loss, true, 1, _) => "Time's up! Your opponent was closer, you lose!",
+                    (Outcome::Win, false, 1, _) => "You win!",
+                    (Outcome::Loss, false, 1, _) => "You lose!",
+                    (Outcome::Win, _, _, Some(_)) => "You win the series!",
+                    (Outcome::Loss, _, _, Some(_)) => "You lose the series!",
+                    (Outcome::Win, _, _, None) => "You win this round! The next one starts soon",
+                    (Outcome::Loss, _, _, None) => "You lose this round! The next one starts soon",
+                    // a draw doesn't count, so the round is played again
+                    (Outcome::Draw, ..) => "Time's up! It's a draw, another round starts soon",
                 }
             }
             State::OpponentLeft => "Opponent left the game",
+            State::Reconnecting => "Reconnecting...",
+            State::RoomNotFound => "Room not found",
+            State::SessionExpired => "Could not rejoin the game",
             State::ConnectionError => "Server connection error",
+            State::Outdated => "A new version is available, please refresh the page",
             _ => return None,
         };
-        let button = matches!(state.get(), State::GameEnd { .. } | State::OpponentLeft)
-            .then(|| view! { <button class="button" on:click=reload>"Play again"</button> });
+        let is_series_end = match state.get() {
+            State::GameEnd { .. } => series.get().winner().is_some(),
+            _ => true,
+        };
+        let rematch_view = match (is_series_end, rematch.get()) {
+            (true, Rematch::Available) => Some(
+                view! { <button class="button" on:click=request_rematch>"Rematch"</button> }
+                    .into_view(),
+            ),
+            (true, Rematch::OpponentRequested) => Some(
+                view! {
+                    <span>"Your opponent wants a rematch"</span>
+                    <button class="button" on:click=request_rematch>"Accept rematch"</button>
+                }
+                .into_view(),
+            ),
+            (true, Rematch::Requested) => {
+                Some(view! { <span>"Waiting for your opponent to accept"</span> }.into_view())
+            }
+            _ => None,
+        };
+        // a private room is gone once its game starts, so reloading won't find it again
+        let button = match state.get() {
+            State::GameEnd { .. } if !is_series_end => None,
+            State::Outdated => {
+                Some(view! { <button class="button" on:click=reload>"Refresh"</button> })
+            }
+            State::GameEnd { .. } | State::OpponentLeft if !is_private => {
+                Some(view! { <button class="button" on:click=reload>"Play again"</button> })
+            }
+            State::GameEnd { .. }
+            | State::OpponentLeft
+            | State::RoomNotFound
+            | State::SessionExpired => {
+                Some(view! { <button class="button" on:click=go_home>"Back to home"</button> })
+            }
+            _ => None,
+        };
+        let rating_view = match state.get() {
+            State::GameEnd {
+                rating: Some(RatingChange { rating, delta }),
+                ..
+            } => Some(view! {
+                <span class="rating">{format!("Rating: {rating} ({delta:+})")}</span>
+            }),
+            _ => None,
+        };
+        let replay_link = matches!(state.get(), State::GameEnd { .. } | State::OpponentLeft)
+            .then(|| game_id.get().map(|game_id| view! { <a class="button" href={format!("/replay/{game_id}")}>"Watch replay"</a> }));
         Some(view! {
             <div class="state">
                 <span>{message}</span>
+                {rating_view}
+                {room_code_view}
+                {rematch_view}
                 {button}
+                {replay_link}
             </div>
         })
     };
 
-    game_view(
-        dimensions,
-        target_view,
-        board_view,
-        opponent_board_view,
-        state_view,
-    )
-}
-
-fn game_view(
-    dimensions: ReadSignal<(i32, i32)>,
-    target_view: impl IntoView,
-    board_view: impl IntoView,
-    opponent_board_view: impl IntoView,
-    state_view: impl IntoView,
-) -> impl IntoView {
-    view! {
-        <div class="background" style={move || format!("--screen-x: {x}; --screen-y: {y}", x = dimensions.get().0, y = dimensions.get().1)}>
-            <p class="target-label">"Target"</p>
-            <div class="target">
-                {target_view}
-            </div>
-            <div class="board">
-                {board_view}
-            </div>
-            <p class="opponent-label">"Opponent"</p>
-            <div class="opponent-board">
-                {opponent_board_view}
-            </div>
-            {state_view}
-        </div>
-    }
-}
-
-fn color_string(color: Color) -> &'static str {
-    match color {
-        Color::White => "white",
-        Color::Yellow => "yellow",
-        Color::Orange => "orange",
-        Color::Red => "red",
-        Color::Green => "green",
-        Color::Blue => "blue",
-    }
-}
-
-struct Board {
-    locations: [(usize, usize); 24],
-    inner: BoardInner<Tile>,
-}
-
-impl Board {
-    fn new(inner: BoardInner) -> Self {
-        let colors = inner.tiles.into_iter().enumerate().flat_map(|(i, row)| {
-            row.into_iter()
-                .enumerate()
-                .filter_map(move |(j, tile)| tile.map(|tile| (i, j, tile)))
-        });
-        let mut locations: [(usize, usize); 24] = Default::default();
-        let mut tiles: BoardTiles<Tile> = Default::default();
-
-        for (idx, (loc, (i, j, color))) in (locations.iter_mut().zip(colors)).enumerate() {
-            tiles[i][j] = Some(Tile { idx, color });
-            *loc = (i, j);
-        }
-
-        Board {
-            locations,
-            inner: BoardInner {
-                tiles,
-                hole: inner.hole,
-            },
-        }
-    }
+    let series_view = move || {
+        let series = series.get();
+        (series.best_of > 1).then(|| {
+            view! {
+                <p class="series-score">
+                    {format!("Best of {}: {} - {}", series.best_of, series.wins[0], series.wins[1])}
+                </p>
+            }
+        })
+    };
 
-    fn matches_target(&self, target: &Target) -> bool {
-        self.inner.matches_target(target)
-    }
+    // counts down with a time limit, up without one, and starts with the first round
+    let clock_view = move || {
+        game_id.get()?;
+        let elapsed = elapsed.get();
+        let (label, time) = match time_limit.get() {
+            Some(limit) => ("Time left", limit.saturating_sub(elapsed)),
+            None => ("Time", elapsed),
+        };
+        let secs = time.as_secs();
+        Some(view! {
+            <p class="clock">{format!("{label}: {}:{:02}", secs / 60, secs % 60)}</p>
+        })
+    };
 
-    fn click_pos(&mut self, pos: (usize, usize)) -> bool {
-        use crate::utils::slide;
+    let rejection_view = move || {
+        let (reason, strikes_left) = rejection.get()?;
+        let message = match strikes_left {
+            Some(0) => format!("Move rejected, {reason}. You forfeit the round"),
+            Some(strikes) => format!("Move rejected, {reason}. {strikes} more forfeit the round"),
+            None => format!("Move rejected, {reason}"),
+        };
+        Some(view! { <p class="move-rejected">{message}</p> })
+    };
 
-        let Self {
-            locations,
-            inner: BoardInner { tiles, hole },
-        } = self;
-        let update = |old: (usize, usize), new: (usize, usize)| {
-            locations[tiles[old.0][old.1].unwrap().idx] = new;
-            tiles[new.0][new.1] = tiles[old.0][old.1];
+    let progress_view = move || {
+        game_id.get()?;
+        let [own_moves, opponent_moves] = moves.get();
+        let matching = move |board: ReadSignal<Option<Board>>| {
+            target.with(|target| {
+                let target = target.as_ref()?;
+                let count = board.with(|board| Some(board.as_ref()?.matching_tiles(target)))?;
+                Some(format!("{count}/{}", target.len() * target.len()))
+            })
         };
+        Some(view! {
+            <table class="progress">
+                <tr><th></th><th>"Moves"</th><th>"Matching"</th></tr>
+                <tr><td>"You"</td><td>{own_moves}</td><td>{matching(board)}</td></tr>
+                <tr><td>"Opponent"</td><td>{opponent_moves}</td><td>{matching(opponent_board)}</td></tr>
+            </table>
+        })
+    };
 
-        if !slide(pos, *hole, update) {
-            return false;
+    let info_view = move || {
+        view! {
+            {series_view}
+            {clock_view}
+            {rejection_view}
         }
+    };
 
-        *hole = pos;
-        true
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-struct TileView {
-    pos: (usize, usize),
-    tile: Tile,
-}
+    let opponent_label = move || {
+        let name = opponent_name().unwrap_or_else(|| "Opponent".to_string());
+        let name = if opponent_connected() {
+            name
+        } else {
+            format!("{name} (reconnecting)")
+        };
+        let bot_badge = opponent_is_bot().then(|| view! { <span class="bot-badge">"BOT"</span> });
+        view! { {name} {bot_badge} }
+    };
 
-#[derive(Debug, Clone, Copy)]
-struct Tile {
-    idx: usize,
-    color: Color,
-}
+    let spectate_link = move || {
+        game_id.get().map(|game_id| {
+            view! { <a href={format!("/spectate/{game_id}")} target="_blank">"Spectate link"</a> }
+        })
+    };
 
-impl From<Tile> for Color {
-    fn from(value: Tile) -> Self {
-        value.color
-    }
+    game_view(
+        dimensions,
+        target_view,
+        board_view,
+        opponent_label,
+        opponent_board_view,
+        state_view,
+        info_view,
+        progress_view,
+        spectate_link,
+    )
 }
@@ -0,0 +1,155 @@
+#![cfg(not(feature = "ssr"))]
+
+use leptos::*;
+
+use super::leaderboard_view;
+use crate::history::{History, MatchSummary};
+use gloo_net::http::Request;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Loading,
+    Loaded,
+    Error,
+}
+
+fn format_duration(ms: u64) -> String {
+    format!("{}.{}s", ms / 1000, ms % 1000 / 100)
+}
+
+fn player_name(summary: &MatchSummary, id: usize) -> String {
+    summary.players[id]
+        .name
+        .clone()
+        .unwrap_or_else(|| "Anonymous".to_string())
+}
+
+#[component]
+pub(super) fn Leaderboard() -> impl IntoView {
+    let (state, set_state) = create_signal(State::Loading);
+    let (history, set_history) = create_signal(None::<History>);
+
+    spawn_local(async move {
+        let response = match Request::get("/history").send().await {
+            Ok(response) if response.ok() => response,
+            Ok(response) => {
+                log!("Failed to fetch history: {}", response.status());
+                set_state(State::Error);
+                return;
+            }
+            Err(e) => {
+                log!("Failed to fetch history: {e}");
+                set_state(State::Error);
+                return;
+            }
+        };
+
+        match response.json::<History>().await {
+            Ok(loaded) => {
+                set_history(Some(loaded));
+                set_state(State::Loaded);
+            }
+            Err(e) => {
+                log!("Failed to read history: {e}");
+                set_state(State::Error);
+            }
+        }
+    });
+
+    let top_players_view = move || {
+        let players = history
+            .with(|history| history.as_ref().map(|history| history.top_players.clone()))
+            .unwrap_or_default();
+        if players.is_empty() {
+            return view! { <p>"Nobody has won a game yet"</p> }.into_view();
+        }
+
+        let rows = players
+            .into_iter()
+            .enumerate()
+            .map(|(rank, player)| {
+                view! {
+                    <tr>
+                        <td>{rank + 1}</td>
+                        <td>{player.name}</td>
+                        <td>{player.rating}</td>
+                        <td>{player.wins}</td>
+                        <td>{player.games - player.wins}</td>
+                        <td>{player.fastest_win_ms.map(format_duration)}</td>
+                    </tr>
+                }
+            })
+            .collect_view();
+        view! {
+            <table>
+                <tr>
+                    <th>"#"</th>
+                    <th>"Player"</th>
+                    <th>"Rating"</th>
+                    <th>"Wins"</th>
+                    <th>"Losses"</th>
+                    <th>"Fastest win"</th>
+                </tr>
+                {rows}
+            </table>
+        }
+        .into_view()
+    };
+
+    let recent_matches_view = move || {
+        let matches = history
+            .with(|history| {
+                history
+                    .as_ref()
+                    .map(|history| history.recent_matches.clone())
+            })
+            .unwrap_or_default();
+        if matches.is_empty() {
+            return view! { <p>"No games played yet"</p> }.into_view();
+        }
+
+        let rows = matches
+            .into_iter()
+            .map(|summary| {
+                let result = match summary.winner {
+                    Some(id) => format!("{} won", player_name(&summary, id)),
+                    None => "No winner".to_string(),
+                };
+                view! {
+                    <tr>
+                        <td>{player_name(&summary, 0)} " (" {summary.players[0].moves} " moves)"</td>
+                        <td>{player_name(&summary, 1)} " (" {summary.players[1].moves} " moves)"</td>
+                        <td>{result}</td>
+                        <td>{format_duration(summary.duration_ms)}</td>
+                        <td><a href={format!("/replay/{}", summary.game_id)}>"Replay"</a></td>
+                    </tr>
+                }
+            })
+            .collect_view();
+        view! {
+            <table>
+                <tr>
+                    <th>"Player 1"</th>
+                    <th>"Player 2"</th>
+                    <th>"Result"</th>
+                    <th>"Duration"</th>
+                    <th></th>
+                </tr>
+                {rows}
+            </table>
+        }
+        .into_view()
+    };
+
+    let state_view = move || match state.get() {
+        State::Loading => Some(view! { <p>"Loading"</p> }),
+        State::Error => Some(view! { <p>"Could not load the leaderboard"</p> }),
+        State::Loaded => None,
+    };
+
+    leaderboard_view(
+        move || (state() == State::Loaded).then(top_players_view),
+        move || (state() == State::Loaded).then(recent_matches_view),
+        state_view,
+    )
+}
@@ -0,0 +1,211 @@
+#![cfg(not(feature = "ssr"))]
+
+use leptos::*;
+use leptos_router::use_query_map;
+
+use super::{
+    board::{make_board_view, make_target_view, Board},
+    connection::{run_connection, ConnectionEnd, Void},
+    game::{board_params, window_dimensions},
+    practice_view,
+};
+use crate::types::{ClientMessage, ServerMessage, Target};
+use core::time::Duration;
+use gloo_net::websocket::futures::WebSocket;
+use tokio::sync::{broadcast, mpsc};
+use wasm_bindgen::{closure::Closure, JsCast};
+use wasmtimer::std::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Connecting,
+    Playing,
+    WaitResult, // target is matched, but server hasn't sent the result yet
+    Solved {
+        moves: u32,
+        time_ms: u64,
+    },
+    ConnectionError,
+    /// The server runs a newer version
+    Outdated,
+}
+
+#[component]
+pub(super) fn Practice() -> impl IntoView {
+    let (shutdown_tx, mut shutdown_rx) = broadcast::channel::<Void>(1);
+
+    let shutdown_tx = store_value(Some(shutdown_tx));
+    let do_shutdown = move || shutdown_tx.set_value(None);
+
+    let window = web_sys::window().expect("should have a window");
+
+    let shutdown_cb = Closure::<dyn Fn()>::new(do_shutdown);
+    window.set_onbeforeunload(Some(shutdown_cb.as_ref().unchecked_ref()));
+    let _shutdown_cb = store_value(shutdown_cb);
+
+    let host = window.location().host().expect("failed to get location");
+    let board_options = use_query_map().with_untracked(board_params);
+
+    let (state, set_state) = create_signal(State::Connecting);
+    let (target, set_target) = create_signal(None::<Target>);
+    let (board, set_board) = create_signal(None::<Board>);
+    let (moves, set_moves) = create_signal(0u32);
+    let (elapsed, set_elapsed) = create_signal(Duration::ZERO);
+    let (dimensions, set_dimensions) = create_signal(window_dimensions());
+    let start = store_value(None::<Instant>);
+
+    let resize_cb = Closure::<dyn Fn()>::new(move || {
+        set_dimensions(window_dimensions());
+    });
+    window.set_onresize(Some(resize_cb.as_ref().unchecked_ref()));
+    let _resize_cb = store_value(resize_cb);
+
+    let window = store_value(window);
+    let reload = move |_| {
+        _ = window().location().reload();
+    };
+
+    // the clock only runs while playing, the final time comes from the server
+    if let Ok(timer) = set_interval_with_handle(
+        move || {
+            if state.get_untracked() == State::Playing {
+                if let Some(start) = start.get_value() {
+                    set_elapsed(start.elapsed());
+                }
+            }
+        },
+        Duration::from_millis(100),
+    ) {
+        on_cleanup(move || timer.clear());
+    }
+
+    let (msg_tx, mut msg_rx) = mpsc::unbounded_channel::<ClientMessage>();
+    let msg_tx = store_value(msg_tx);
+
+    let handle_server_message = move |msg: ServerMessage| match msg {
+        ServerMessage::PracticeStart { target, board } => {
+            if state.get_untracked() != State::Connecting {
+                log!("Got practice start but not connecting");
+                return;
+            }
+
+            set_target(Some(target));
+            set_board(Some(Board::new(board)));
+            start.set_value(Some(Instant::now()));
+            set_state(State::Playing);
+        }
+        ServerMessage::PracticeEnd { moves, time_ms } => {
+            set_elapsed(Duration::from_millis(time_ms));
+            set_state(State::Solved { moves, time_ms });
+            do_shutdown();
+        }
+        ServerMessage::MoveRejected { reason, board, .. } => {
+            if !matches!(state.get_untracked(), State::Playing | State::WaitResult) {
+                log!("Got move rejection but not practicing");
+                return;
+            }
+            log!("Click was rejected: {reason}");
+
+            // the rejected click was counted when it was made
+            set_moves.update(|moves| *moves = moves.saturating_sub(1));
+            set_board(Some(Board::new(board)));
+            set_state(State::Playing);
+        }
+        _ => log!("Got unexpected message while practicing"),
+    };
+
+    spawn_local(async move {
+        let end = match WebSocket::open(&format!(
+            "wss://{host}/connect?practice=true{board_options}"
+        )) {
+            Ok(ws) => {
+                run_connection(ws, &mut msg_rx, &mut shutdown_rx, handle_server_message).await
+            }
+            Err(e) => {
+                log!("Failed to connect: {e}");
+                ConnectionEnd::Lost
+            }
+        };
+
+        match end {
+            ConnectionEnd::Shutdown => {}
+            ConnectionEnd::Lost => {
+                set_state(State::ConnectionError);
+                do_shutdown();
+            }
+            ConnectionEnd::Outdated => {
+                set_state(State::Outdated);
+                do_shutdown();
+            }
+        }
+    });
+
+    let handle_click = move |idx: usize| {
+        if state() != State::Playing {
+            return;
+        }
+        set_board.update(|board| {
+            let board = board.as_mut().expect("playing but no board");
+            let pos = board.locations[idx];
+            if !board.click_pos(pos) {
+                return;
+            }
+            _ = msg_tx.with_value(|msg_tx| msg_tx.send(ClientMessage::Click { pos }));
+            set_moves.update(|moves| *moves += 1);
+
+            let is_solved = target.with(|target| {
+                board.matches_target(target.as_ref().expect("playing but no target"))
+            });
+            if is_solved {
+                set_state(State::WaitResult);
+            }
+        })
+    };
+
+    let stats_view = move || {
+        let elapsed = elapsed();
+        view! {
+            <span>{format!("Time: {}.{}s", elapsed.as_secs(), elapsed.subsec_millis() / 100)}</span>
+            <span>{format!("Moves: {}", moves())}</span>
+        }
+    };
+
+    let state_view = move || {
+        let (message, button) = match state.get() {
+            State::Connecting => ("Starting practice".to_string(), None),
+            State::Solved { moves, time_ms } => (
+                format!(
+                    "Solved in {}.{:03}s with {moves} moves!",
+                    time_ms / 1000,
+                    time_ms % 1000
+                ),
+                Some("Practice again"),
+            ),
+            State::ConnectionError => (
+                "Server connection error".to_string(),
+                Some("Practice again"),
+            ),
+            State::Outdated => (
+                "A new version is available, please refresh the page".to_string(),
+                Some("Refresh"),
+            ),
+            State::Playing | State::WaitResult => return None,
+        };
+        let button =
+            button.map(|label| view! { <button class="button" on:click=reload>{label}</button> });
+        Some(view! {
+            <div class="state">
+                <span>{message}</span>
+                {button}
+            </div>
+        })
+    };
+
+    practice_view(
+        dimensions,
+        make_target_view(target),
+        make_board_view(board, handle_click, || None),
+        stats_view,
+        state_view,
+    )
+}
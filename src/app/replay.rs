@@ -0,0 +1,227 @@
+#![cfg(not(feature = "ssr"))]
+
+use leptos::*;
+use leptos_router::use_params_map;
+
+use super::{
+    board::{make_board_view, make_target_view, Board},
+    game::window_dimensions,
+    spectate_view,
+};
+use crate::{
+    replay::Replay,
+    types::{GameId, Target},
+};
+use core::time::Duration;
+use gloo_net::http::Request;
+use wasm_bindgen::{closure::Closure, JsCast};
+
+/// How often the playback clock ticks
+const TICK: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Loading,
+    Ready,
+    NotFound,
+    Invalid,
+}
+
+#[component]
+pub(super) fn ReplayViewer() -> impl IntoView {
+    let window = web_sys::window().expect("should have a window");
+
+    let (status, set_status) = create_signal(Status::Loading);
+    let (replay, set_replay) = create_signal(None::<Replay>);
+    let (target, set_target) = create_signal(None::<Target>);
+    let (left_board, set_left_board) = create_signal(None::<Board>);
+    let (right_board, set_right_board) = create_signal(None::<Board>);
+    // number of clicks applied to the boards
+    let (step, set_step) = create_signal(0);
+    let (playing, set_playing) = create_signal(false);
+    let (dimensions, set_dimensions) = create_signal(window_dimensions());
+    // playback position in milliseconds since the start of the game
+    let clock = store_value(0);
+
+    let resize_cb = Closure::<dyn Fn()>::new(move || {
+        set_dimensions(window_dimensions());
+    });
+    window.set_onresize(Some(resize_cb.as_ref().unchecked_ref()));
+    let _resize_cb = store_value(resize_cb);
+
+    let game_id = use_params_map()
+        .with_untracked(|params| params.get("id").and_then(|id| id.parse::<GameId>().ok()));
+
+    let reset = move |replay: &Replay| {
+        let [left, right] = replay.boards.clone();
+        set_left_board(Some(Board::new(left)));
+        set_right_board(Some(Board::new(right)));
+    };
+
+    spawn_local(async move {
+        let Some(game_id) = game_id else {
+            set_status(Status::NotFound);
+            return;
+        };
+
+        let response = match Request::get(&format!("/replays/{game_id}")).send().await {
+            Ok(response) if response.ok() => response,
+            Ok(_) => {
+                set_status(Status::NotFound);
+                return;
+            }
+            Err(e) => {
+                log!("Failed to fetch replay: {e}");
+                set_status(Status::NotFound);
+                return;
+            }
+        };
+
+        let replay = match response.text().await.map(|json| Replay::from_json(&json)) {
+            Ok(Ok(replay)) => replay,
+            Ok(Err(e)) => {
+                log!("{e}");
+                set_status(Status::Invalid);
+                return;
+            }
+            Err(e) => {
+                log!("Failed to read replay: {e}");
+                set_status(Status::Invalid);
+                return;
+            }
+        };
+
+        set_target(Some(replay.target.clone()));
+        reset(&replay);
+        set_replay(Some(replay));
+        set_status(Status::Ready);
+    });
+
+    // moves the boards to just after the given number of clicks
+    let seek = move |new_step: usize| {
+        replay.with_untracked(|replay| {
+            let Some(replay) = replay else {
+                return;
+            };
+            let new_step = new_step.min(replay.clicks.len());
+
+            // boards can't go back, so they're rebuilt from the start instead
+            let mut from = step.get_untracked();
+            if new_step < from {
+                reset(replay);
+                from = 0;
+            }
+
+            for click in &replay.clicks[from..new_step] {
+                let set_board = if click.player == 0 {
+                    set_left_board
+                } else {
+                    set_right_board
+                };
+                set_board.update(|board| {
+                    board
+                        .as_mut()
+                        .expect("replay but no board")
+                        .click_pos(click.pos);
+                });
+            }
+
+            set_step(new_step);
+            clock.set_value(
+                new_step
+                    .checked_sub(1)
+                    .map_or(0, |i| replay.clicks[i].time_ms),
+            );
+        })
+    };
+
+    if let Ok(timer) = set_interval_with_handle(
+        move || {
+            if !playing.get_untracked() {
+                return;
+            }
+
+            let time_ms = clock.get_value() + TICK.as_millis() as u64;
+            let (new_step, total) = replay.with_untracked(|replay| {
+                let clicks = replay.as_ref().map_or(&[][..], |replay| &replay.clicks[..]);
+                let new_step = clicks.partition_point(|click| click.time_ms <= time_ms);
+                (new_step, clicks.len())
+            });
+
+            if new_step != step.get_untracked() {
+                seek(new_step);
+            }
+            clock.set_value(time_ms);
+            if new_step == total {
+                set_playing(false);
+            }
+        },
+        TICK,
+    ) {
+        on_cleanup(move || timer.clear());
+    }
+
+    let total =
+        move || replay.with(|replay| replay.as_ref().map_or(0, |replay| replay.clicks.len()));
+    let toggle_play = move |_| {
+        if step.get_untracked() == total() {
+            seek(0);
+        }
+        set_playing.update(|playing| *playing = !*playing);
+    };
+
+    let controls_view = move || {
+        (status.get() == Status::Ready).then(|| {
+            let winner = move || {
+                (step() == total()).then(|| {
+                    replay.with(|replay| match replay.as_ref().and_then(|replay| replay.winner) {
+                        Some(0) => "Player 1 wins!",
+                        Some(_) => "Player 2 wins!",
+                        None => "Nobody won",
+                    })
+                })
+            };
+
+            view! {
+                <div class="replay-controls">
+                    <button class="button" on:click=move |_| seek(0)>"Restart"</button>
+                    <button class="button" on:click=move |_| seek(step.get_untracked().saturating_sub(1))>"Back"</button>
+                    <button class="button" on:click=toggle_play>{move || if playing() { "Pause" } else { "Play" }}</button>
+                    <button class="button" on:click=move |_| seek(step.get_untracked() + 1)>"Forward"</button>
+                    <span>{move || format!("Click {} of {}", step(), total())}</span>
+                    <span>{winner}</span>
+                </div>
+            }
+        })
+    };
+
+    let state_view = move || {
+        let message = match status.get() {
+            Status::Loading => "Loading replay",
+            Status::NotFound => "Replay not found",
+            Status::Invalid => "This replay can't be played",
+            Status::Ready => return None,
+        };
+        Some(view! {
+            <div class="state">
+                <span>{message}</span>
+                <a class="button" href="/">"Back to home"</a>
+            </div>
+        })
+    };
+
+    let ignore_click = |_| {};
+    let no_drag = || None;
+
+    spectate_view(
+        dimensions,
+        make_target_view(target),
+        ["Player 1", "Player 2"],
+        [
+            make_board_view(left_board, ignore_click, no_drag),
+            make_board_view(right_board, ignore_click, no_drag),
+        ],
+        state_view,
+        controls_view,
+    )
+}
@@ -0,0 +1,137 @@
+//! Player settings kept in the browser's local storage.
+//!
+//! These are only read and written in the browser, never while rendering on the server.
+
+use std::{fmt, str::FromStr};
+
+use web_sys::Storage;
+
+const NAME_KEY: &str = "name";
+const CURSOR_KEY: &str = "keyboard_cursor";
+const SYMBOLS_KEY: &str = "tile_symbols";
+const COLOR_SCHEME_KEY: &str = "color_scheme";
+
+fn local_storage() -> Option<Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// The display name sent when joining a game, if the player picked one.
+pub(super) fn load_name() -> Option<String> {
+    let name = local_storage()?.get_item(NAME_KEY).ok()??;
+    (!name.trim().is_empty()).then_some(name)
+}
+
+pub(super) fn save_name(name: &str) {
+    let Some(storage) = local_storage() else {
+        return;
+    };
+    // not being able to save only means the name has to be picked again later
+    _ = storage.set_item(NAME_KEY, name);
+}
+
+/// The secret the player's rating is kept under, made up the first time it's needed.
+#[cfg(not(feature = "ssr"))]
+pub(super) fn load_player_key() -> Option<String> {
+    use std::fmt::Write;
+
+    const PLAYER_KEY_KEY: &str = "player_key";
+
+    let storage = local_storage()?;
+    if let Some(key) = storage.get_item(PLAYER_KEY_KEY).ok()? {
+        return Some(key);
+    }
+
+    let mut bytes = [0u8; 16];
+    web_sys::window()?
+        .crypto()
+        .ok()?
+        .get_random_values_with_u8_array(&mut bytes)
+        .ok()?;
+    let key = bytes.iter().fold(String::new(), |mut key, byte| {
+        _ = write!(key, "{byte:02x}");
+        key
+    });
+    // a key that isn't kept would start a new rating every game
+    storage.set_item(PLAYER_KEY_KEY, &key).ok()?;
+    Some(key)
+}
+
+/// Whether a cursor is shown on the board, moved with the arrow keys and clicked with enter.
+pub(super) fn load_cursor() -> bool {
+    local_storage()
+        .and_then(|storage| storage.get_item(CURSOR_KEY).ok()?)
+        .is_some_and(|value| value == "true")
+}
+
+pub(super) fn save_cursor(show: bool) {
+    let Some(storage) = local_storage() else {
+        return;
+    };
+    _ = storage.set_item(CURSOR_KEY, &show.to_string());
+}
+
+/// How tiles are drawn, for players who find some of the colors hard to tell apart
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct TileStyle {
+    /// Each color gets its own symbol
+    pub(super) symbols: bool,
+    pub(super) color_scheme: ColorScheme,
+}
+
+impl TileStyle {
+    /// Classes for an element around the tiles, which the stylesheet draws them by.
+    pub(super) fn class(&self) -> String {
+        let symbols = if self.symbols { "symbols" } else { "" };
+        format!("{symbols} {}", self.color_scheme)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ColorScheme {
+    #[default]
+    Standard,
+    /// Colors that differ in lightness as well as hue
+    HighContrast,
+}
+
+impl fmt::Display for ColorScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorScheme::Standard => "standard",
+            ColorScheme::HighContrast => "high-contrast",
+        })
+    }
+}
+
+impl FromStr for ColorScheme {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(ColorScheme::Standard),
+            "high-contrast" => Ok(ColorScheme::HighContrast),
+            _ => Err(()),
+        }
+    }
+}
+
+pub(super) fn load_tile_style() -> TileStyle {
+    let Some(storage) = local_storage() else {
+        return TileStyle::default();
+    };
+    let load = |key| storage.get_item(key).ok().flatten();
+    TileStyle {
+        symbols: load(SYMBOLS_KEY).is_some_and(|value| value == "true"),
+        color_scheme: load(COLOR_SCHEME_KEY)
+            .and_then(|scheme| scheme.parse().ok())
+            .unwrap_or_default(),
+    }
+}
+
+pub(super) fn save_tile_style(style: TileStyle) {
+    let Some(storage) = local_storage() else {
+        return;
+    };
+    _ = storage.set_item(SYMBOLS_KEY, &style.symbols.to_string());
+    _ = storage.set_item(COLOR_SCHEME_KEY, &style.color_scheme.to_string());
+}
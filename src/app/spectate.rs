@@ -0,0 +1,206 @@
+#![cfg(not(feature = "ssr"))]
+
+use leptos::*;
+use leptos_router::use_params_map;
+
+use super::{
+    board::{make_board_view, make_target_view, Board},
+    connection::{run_connection, ConnectionEnd, Void},
+    game::window_dimensions,
+    spectate_view,
+};
+use crate::types::{ClientMessage, GameId, Series, ServerMessage, Target};
+use gloo_net::websocket::futures::WebSocket;
+use tokio::sync::{broadcast, mpsc};
+use wasm_bindgen::{closure::Closure, JsCast};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Connecting,
+    Watching,
+    GameOver {
+        winner: Option<usize>,
+    },
+    GameNotFound,
+    ConnectionError,
+    /// The server runs a newer version
+    Outdated,
+}
+
+#[component]
+pub(super) fn Spectate() -> impl IntoView {
+    let (shutdown_tx, mut shutdown_rx) = broadcast::channel::<Void>(1);
+
+    let shutdown_tx = store_value(Some(shutdown_tx));
+    let do_shutdown = move || shutdown_tx.set_value(None);
+
+    let window = web_sys::window().expect("should have a window");
+
+    let shutdown_cb = Closure::<dyn Fn()>::new(do_shutdown);
+    window.set_onbeforeunload(Some(shutdown_cb.as_ref().unchecked_ref()));
+    let _shutdown_cb = store_value(shutdown_cb);
+
+    let host = window.location().host().expect("failed to get location");
+
+    let (state, set_state) = create_signal(State::Connecting);
+    let (target, set_target) = create_signal(None::<Target>);
+    let (left_board, set_left_board) = create_signal(None::<Board>);
+    let (right_board, set_right_board) = create_signal(None::<Board>);
+    let (names, set_names) = create_signal([None::<String>, None::<String>]);
+    let (series, set_series) = create_signal(Series::new(1));
+    // every round of a series has its own id, and replay
+    let (round_id, set_round_id) = create_signal(None::<GameId>);
+    let (dimensions, set_dimensions) = create_signal(window_dimensions());
+
+    let resize_cb = Closure::<dyn Fn()>::new(move || {
+        set_dimensions(window_dimensions());
+    });
+    window.set_onresize(Some(resize_cb.as_ref().unchecked_ref()));
+    let _resize_cb = store_value(resize_cb);
+
+    let game_id = use_params_map()
+        .with_untracked(|params| params.get("id").and_then(|id| id.parse::<GameId>().ok()));
+
+    // spectators only ever send pings, but run_connection stops once this is dropped
+    let (msg_tx, mut msg_rx) = mpsc::unbounded_channel::<ClientMessage>();
+    let _msg_tx = store_value(msg_tx);
+
+    let handle_server_message = move |msg: ServerMessage| match msg {
+        // sent again at the start of every round of a series
+        ServerMessage::SpectateStart(start) => {
+            if !matches!(
+                state.get_untracked(),
+                State::Connecting | State::GameOver { .. }
+            ) {
+                log!("Got spectate start but already watching");
+                return;
+            }
+
+            let [left, right] = start.boards;
+            set_target(Some(start.target));
+            set_left_board(Some(Board::new(left)));
+            set_right_board(Some(Board::new(right)));
+            set_names(start.names);
+            set_series(start.series);
+            set_round_id(Some(start.game_id));
+            set_state(State::Watching);
+        }
+        ServerMessage::PlayerClick { player, pos, .. } => {
+            if state.get_untracked() != State::Watching {
+                log!("Got player click but not watching");
+                return;
+            }
+
+            let set_board = if player == 0 {
+                set_left_board
+            } else {
+                set_right_board
+            };
+            set_board.update(|board| {
+                board
+                    .as_mut()
+                    .expect("watching but no board")
+                    .click_pos(pos);
+            });
+        }
+        // the connection stays open in case there's another round
+        ServerMessage::GameOver { winner, series } => {
+            set_series(series);
+            set_state(State::GameOver { winner });
+        }
+        ServerMessage::GameNotFound => {
+            set_state(State::GameNotFound);
+            do_shutdown();
+        }
+        _ => log!("Got player message while spectating"),
+    };
+
+    match game_id {
+        Some(game_id) => spawn_local(async move {
+            let end = match WebSocket::open(&format!("wss://{host}/connect?spectate={game_id}")) {
+                Ok(ws) => {
+                    run_connection(ws, &mut msg_rx, &mut shutdown_rx, handle_server_message).await
+                }
+                Err(e) => {
+                    log!("Failed to connect: {e}");
+                    ConnectionEnd::Lost
+                }
+            };
+
+            // the server closes the connection once the game is over for good
+            let is_over = matches!(state.get_untracked(), State::GameOver { .. });
+            match (end, is_over) {
+                (ConnectionEnd::Lost, false) => set_state(State::ConnectionError),
+                (ConnectionEnd::Outdated, _) => set_state(State::Outdated),
+                _ => {}
+            }
+            do_shutdown();
+        }),
+        None => set_state(State::GameNotFound),
+    }
+
+    let player_label = move |id: usize| {
+        names
+            .with(|names| names[id].clone())
+            .unwrap_or_else(|| format!("Player {}", id + 1))
+    };
+
+    let series_view = move || {
+        let series = series.get();
+        (series.best_of > 1).then(|| {
+            view! {
+                <p class="series-score">
+                    {format!("Best of {}: {} - {}", series.best_of, series.wins[0], series.wins[1])}
+                </p>
+            }
+        })
+    };
+
+    let state_view = move || {
+        let message = match state.get() {
+            State::Connecting => "Connecting to game".to_string(),
+            State::GameOver {
+                winner: Some(winner),
+            } if series.get().best_of > 1 => {
+                let series = series.get();
+                match series.winner() {
+                    Some(_) => format!("{} wins the series!", player_label(winner)),
+                    None => format!("{} wins this round!", player_label(winner)),
+                }
+            }
+            State::GameOver {
+                winner: Some(winner),
+            } => format!("{} wins!", player_label(winner)),
+            State::GameOver { winner: None } => "Game over".to_string(),
+            State::GameNotFound => "Game not found".to_string(),
+            State::ConnectionError => "Server connection error".to_string(),
+            State::Outdated => "A new version is available, please refresh the page".to_string(),
+            State::Watching => return None,
+        };
+        let replay_link = matches!(state.get(), State::GameOver { .. })
+            .then(|| round_id.get().map(|game_id| view! { <a class="button" href={format!("/replay/{game_id}")}>"Watch replay"</a> }));
+        Some(view! {
+            <div class="state">
+                <span>{message}</span>
+                {replay_link}
+                <a class="button" href="/">"Back to home"</a>
+            </div>
+        })
+    };
+
+    // spectators can't drag or click, and both views need the same type to go in an array
+    let ignore_click = |_| {};
+    let no_drag = || None;
+
+    spectate_view(
+        dimensions,
+        make_target_view(target),
+        [0, 1].map(|id| move || player_label(id)),
+        [
+            make_board_view(left_board, ignore_click, no_drag),
+            make_board_view(right_board, ignore_click, no_drag),
+        ],
+        state_view,
+        series_view,
+    )
+}
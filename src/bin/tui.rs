@@ -0,0 +1,568 @@
+//! Plays in the terminal, against the same server and over the same protocol as the web client.
+//!
+//! Arrow keys slide the tile next to the hole into it, and typing a row and then a column,
+//! like `24`, clicks that position. `r` asks for a rematch once a series is over, and `q`
+//! quits.
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Color as TermColor, Print, ResetColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use start_axum::types::{
+    BoardInner, BoardTiles, Capabilities, ClientMessage, Color, GameStart, Hello, Outcome,
+    RatingChange, Series, ServerMessage, Target, PROTOCOL_VERSION,
+};
+use tokio::{net::TcpStream, select, time::interval};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+const USAGE: &str = "\
+usage: rubiks-race-tui [--server URL] [--name NAME] [--key KEY] [--room CODE | --create | --bot DIFFICULTY | --practice]
+
+Without a room, bot or practice, you join the public queue. Public games are rated when you
+give a key, a secret of 16 to 64 letters and digits that your rating is kept under.";
+
+const DEFAULT_SERVER: &str = "wss://rubiks-race.fly.dev";
+/// Keeps idle connections from being closed, like the web client does
+const PING_INTERVAL: Duration = Duration::from_secs(50);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+struct Args {
+    server: String,
+    name: Option<String>,
+    /// Query string for `/connect`, which picks the kind of game
+    query: String,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = std::env::args().skip(1);
+    let mut server = DEFAULT_SERVER.to_string();
+    let mut name = None;
+    let mut key = None;
+    let mut query = String::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--server" => server = value()?,
+            "--name" => name = Some(value()?),
+            "--key" => key = Some(url_safe(&value()?)),
+            "--room" => query = format!("?room={}", url_safe(&value()?)),
+            "--create" => query = "?create_room=true".to_string(),
+            "--bot" => query = format!("?bot={}", url_safe(&value()?)),
+            "--practice" => query = "?practice=true".to_string(),
+            _ => return Err(format!("unknown argument {arg}")),
+        }
+    }
+    // only public games are rated
+    if let (true, Some(key)) = (query.is_empty(), key) {
+        query = format!("?key={key}");
+    }
+
+    Ok(Args {
+        server: server.trim_end_matches('/').to_string(),
+        name,
+        query,
+    })
+}
+
+/// Room codes, difficulties and keys are plain words, so anything else can go.
+fn url_safe(value: &str) -> String {
+    value.chars().filter(char::is_ascii_alphanumeric).collect()
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let url = format!("{}/connect{}", args.server, args.query);
+    let ws = match connect(&url, args.name).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("Could not join a game: {e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    let result = match Terminal::enter() {
+        Ok(_terminal) => run(ws).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = result {
+        eprintln!("{e:#}");
+        std::process::exit(1);
+    }
+}
+
+/// Opens the connection and goes through the handshake, up to and including the `Join`.
+async fn connect(url: &str, name: Option<String>) -> anyhow::Result<Socket> {
+    let (mut ws, _) = connect_async(url)
+        .await
+        .with_context(|| format!("failed to connect to {url}"))?;
+
+    send(&mut ws, &Hello::current(Capabilities::ALL)).await?;
+    let hello: Hello = match ws.next().await {
+        Some(Ok(Message::Binary(msg))) => bincode::deserialize(&msg)?,
+        _ => bail!("the server closed the connection"),
+    };
+    // the server answers with our own version if it can talk to us
+    if hello.version != PROTOCOL_VERSION {
+        bail!(
+            "the server doesn't support protocol version {PROTOCOL_VERSION}, it speaks {}",
+            hello.version
+        );
+    }
+
+    send(&mut ws, &ClientMessage::Join { name }).await?;
+    Ok(ws)
+}
+
+async fn send(ws: &mut Socket, msg: &impl Serialize) -> anyhow::Result<()> {
+    let msg = bincode::serialize(msg).expect("failed to serialize");
+    ws.send(Message::Binary(msg)).await?;
+    Ok(())
+}
+
+async fn run(mut ws: Socket) -> anyhow::Result<()> {
+    let mut game = Game::default();
+    let mut events = EventStream::new();
+    // redraws the clock
+    let mut tick = interval(Duration::from_secs(1));
+    let mut ping = interval(PING_INTERVAL);
+    let mut connected = true;
+
+    loop {
+        game.draw()?;
+
+        select! {
+            msg = ws.next(), if connected => match msg {
+                Some(Ok(Message::Binary(msg))) => {
+                    let msg = bincode::deserialize(&msg).context("got an invalid message")?;
+                    game.handle_message(msg);
+                }
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                _ => {
+                    connected = false;
+                    game.disconnected();
+                }
+            },
+            event = events.next() => {
+                let Some(event) = event else { break; };
+                let Event::Key(key) = event? else { continue; };
+                match game.handle_key(key) {
+                    Some(Action::Send(msg)) if connected => send(&mut ws, &msg).await?,
+                    Some(Action::Send(_)) | None => {}
+                    Some(Action::Quit) => break,
+                }
+            }
+            _ = tick.tick() => {}
+            _ = ping.tick(), if connected => send(&mut ws, &ClientMessage::Ping).await?,
+        }
+    }
+
+    _ = ws.close(None).await;
+    Ok(())
+}
+
+/// Raw mode on an alternate screen, which is restored when dropped.
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        _ = terminal::disable_raw_mode();
+    }
+}
+
+enum Action {
+    Send(ClientMessage),
+    Quit,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    #[default]
+    Waiting,
+    Playing,
+    /// The board matches the target, but the server hasn't said so yet
+    Matched,
+    Over,
+}
+
+/// Everything on screen, kept in step with the server.
+#[derive(Default)]
+struct Game {
+    phase: Phase,
+    target: Option<Target>,
+    board: Option<BoardInner>,
+    opponent_board: Option<BoardInner>,
+    opponent_name: Option<String>,
+    opponent_is_bot: bool,
+    opponent_connected: bool,
+    series: Option<Series>,
+    rematch_available: bool,
+    /// The server's round time as of the last message, and when that message arrived
+    clock: Option<(Duration, Instant)>,
+    time_limit: Option<Duration>,
+    /// The row of a position being typed
+    typed_row: Option<usize>,
+    status: String,
+}
+
+impl Game {
+    fn start(&mut self, start: GameStart) {
+        self.phase = Phase::Playing;
+        self.target = Some(start.target);
+        self.board = Some(start.board);
+        self.opponent_board = Some(start.opponent_board);
+        self.opponent_name = start.opponent_name;
+        self.opponent_is_bot = start.opponent_is_bot;
+        self.opponent_connected = true;
+        self.series = Some(start.series);
+        self.rematch_available = false;
+        self.time_limit = start.time_limit_ms.map(Duration::from_millis);
+        self.sync_clock(start.elapsed_ms);
+        self.typed_row = None;
+        self.status.clear();
+    }
+
+    fn sync_clock(&mut self, elapsed_ms: u64) {
+        self.clock = Some((Duration::from_millis(elapsed_ms), Instant::now()));
+    }
+
+    /// Time into the round, which stops once the round is over.
+    fn elapsed(&self) -> Option<Duration> {
+        let (base, at) = self.clock?;
+        Some(match self.phase {
+            Phase::Playing | Phase::Matched => base + at.elapsed(),
+            Phase::Waiting | Phase::Over => base,
+        })
+    }
+
+    fn end_round(&mut self, status: String) {
+        if let Some(elapsed) = self.elapsed() {
+            self.clock = Some((elapsed, Instant::now()));
+        }
+        self.phase = Phase::Over;
+        self.status = status;
+    }
+
+    fn disconnected(&mut self) {
+        self.end_round("Lost the connection to the server, press q to quit".to_string());
+        self.rematch_available = false;
+    }
+
+    fn handle_message(&mut self, msg: ServerMessage) {
+        match msg {
+            ServerMessage::GameStart(start) | ServerMessage::Resync(start) => self.start(start),
+            ServerMessage::PracticeStart { target, board } => {
+                self.phase = Phase::Playing;
+                self.target = Some(target);
+                self.board = Some(board);
+                self.sync_clock(0);
+            }
+            ServerMessage::OpponentClick { pos, time_ms } => {
+                if let Some(board) = &mut self.opponent_board {
+                    board.click(pos);
+                }
+                self.sync_clock(time_ms);
+            }
+            ServerMessage::Clock { elapsed_ms } => self.sync_clock(elapsed_ms),
+            ServerMessage::MoveRejected {
+                reason,
+                board,
+                strikes_left,
+                ..
+            } => {
+                self.board = Some(board);
+                if self.phase == Phase::Matched {
+                    self.phase = Phase::Playing;
+                }
+                self.status = match strikes_left {
+                    Some(0) => format!("Move rejected, {reason}. You forfeit the round"),
+                    Some(strikes) => {
+                        format!("Move rejected, {reason}. {strikes} more forfeit the round")
+                    }
+                    None => format!("Move rejected, {reason}"),
+                };
+            }
+            ServerMessage::GameEnd {
+                outcome,
+                time_up,
+                rating,
+                series,
+            } => {
+                let result = match (outcome, time_up) {
+                    (Outcome::Win, false) => "You win!",
+                    (Outcome::Loss, false) => "You lose!",
+                    (Outcome::Win, true) => "Time's up! You were closer, you win!",
+                    (Outcome::Loss, true) => "Time's up! Your opponent was closer, you lose!",
+                    (Outcome::Draw, _) => "Time's up! It's a draw",
+                };
+                let rating = rating
+                    .map(|RatingChange { rating, delta }| format!(" Rating: {rating} ({delta:+})"))
+                    .unwrap_or_default();
+                let next = match series.winner() {
+                    Some(_) => " Press r for a rematch",
+                    None => " The next round starts soon",
+                };
+                self.series = Some(series);
+                self.rematch_available = series.winner().is_some();
+                self.end_round(format!("{result}{rating}{next}"));
+            }
+            ServerMessage::PracticeEnd { moves, time_ms } => {
+                self.sync_clock(time_ms);
+                self.end_round(format!(
+                    "Solved in {}.{:03}s with {moves} moves!",
+                    time_ms / 1000,
+                    time_ms % 1000
+                ));
+            }
+            ServerMessage::RoomCreated { code } => {
+                self.status = format!("Room code: {code}, waiting for an opponent");
+            }
+            ServerMessage::RematchRequested => {
+                self.status = "Your opponent wants a rematch, press r to accept".to_string();
+            }
+            ServerMessage::OpponentDisconnected => self.opponent_connected = false,
+            ServerMessage::OpponentReconnected => self.opponent_connected = true,
+            ServerMessage::OpponentLeft => {
+                self.rematch_available = false;
+                self.end_round("Your opponent left the game".to_string());
+            }
+            ServerMessage::RoomNotFound => self.end_round("Room not found".to_string()),
+            ServerMessage::SessionExpired => {
+                self.end_round("Could not rejoin the game".to_string());
+            }
+            ServerMessage::SpectateStart(_)
+            | ServerMessage::PlayerClick { .. }
+            | ServerMessage::GameOver { .. }
+            | ServerMessage::GameNotFound => {}
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                Some(Action::Quit)
+            }
+            KeyCode::Char('q') | KeyCode::Esc => Some(Action::Quit),
+            KeyCode::Char('r') if self.rematch_available => {
+                self.rematch_available = false;
+                self.status = "Waiting for your opponent to accept".to_string();
+                Some(Action::Send(ClientMessage::Rematch))
+            }
+            KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right => {
+                let (row, col) = self.board.as_ref()?.hole;
+                // the tile on the far side of the hole slides into it
+                let pos = match key.code {
+                    KeyCode::Up => (row + 1, col),
+                    KeyCode::Down => (row.checked_sub(1)?, col),
+                    KeyCode::Left => (row, col + 1),
+                    _ => (row, col.checked_sub(1)?),
+                };
+                self.click(pos)
+            }
+            KeyCode::Char(digit @ '1'..='9') => {
+                let index = digit as usize - '1' as usize;
+                match self.typed_row.take() {
+                    Some(row) => self.click((row, index)),
+                    None => {
+                        self.typed_row = Some(index);
+                        None
+                    }
+                }
+            }
+            KeyCode::Backspace => {
+                self.typed_row = None;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves the tiles right away, the server only tells us if it disagrees.
+    fn click(&mut self, pos: (usize, usize)) -> Option<Action> {
+        if self.phase != Phase::Playing {
+            return None;
+        }
+        let board = self.board.as_mut()?;
+        if pos.0 >= board.tiles.len() || pos.1 >= board.tiles.len() || !board.click(pos) {
+            self.status = "Nothing can slide from there".to_string();
+            return None;
+        }
+
+        self.status.clear();
+        let target = self.target.as_ref()?;
+        if board.matches_target(target) {
+            self.phase = Phase::Matched;
+            self.status = "Matched! Waiting for the server".to_string();
+        }
+        Some(Action::Send(ClientMessage::Click { pos }))
+    }
+
+    fn draw(&self) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        queue!(
+            out,
+            Clear(ClearType::All),
+            MoveTo(2, 0),
+            Print("Rubik's Race")
+        )?;
+
+        let (Some(target), Some(board)) = (&self.target, &self.board) else {
+            let status = match self.status.as_str() {
+                "" => "Waiting for an opponent",
+                status => status,
+            };
+            queue!(
+                out,
+                MoveTo(2, 2),
+                Print(status),
+                MoveTo(2, 4),
+                Print("q: quit")
+            )?;
+            return out.flush();
+        };
+
+        let size = board.tiles.len() as u16;
+        let target_tiles: BoardTiles = target
+            .iter()
+            .map(|row| row.iter().copied().map(Some).collect())
+            .collect();
+        // the target lines up with the middle of the board
+        let target_y = 4 + (size - target.len() as u16) / 2;
+        let board_x = 4 + TILE_WIDTH * target.len() as u16 + 6;
+        let opponent_x = board_x + TILE_WIDTH * size + 6;
+
+        queue!(out, MoveTo(2, 2), Print("Target"))?;
+        draw_tiles(&mut out, 2, target_y, &target_tiles)?;
+
+        queue!(out, MoveTo(board_x, 2), Print("You"))?;
+        for i in 0..size {
+            let label = (i + 1).to_string();
+            queue!(
+                out,
+                MoveTo(board_x + TILE_WIDTH * i, 3),
+                Print(&label),
+                MoveTo(board_x - 2, 4 + i),
+                Print(&label)
+            )?;
+        }
+        draw_tiles(&mut out, board_x, 4, &board.tiles)?;
+
+        if let Some(opponent_board) = &self.opponent_board {
+            let mut label = self
+                .opponent_name
+                .clone()
+                .unwrap_or_else(|| "Opponent".to_string());
+            if self.opponent_is_bot {
+                label += " [BOT]";
+            }
+            if !self.opponent_connected {
+                label += " (reconnecting)";
+            }
+            queue!(out, MoveTo(opponent_x, 2), Print(label))?;
+            draw_tiles(&mut out, opponent_x, 4, &opponent_board.tiles)?;
+        }
+
+        let mut lines = Vec::new();
+        if let Some(series) = self.series.filter(|series| series.best_of > 1) {
+            lines.push(format!(
+                "Best of {}: {} - {}",
+                series.best_of, series.wins[0], series.wins[1]
+            ));
+        }
+        if let Some(elapsed) = self.elapsed() {
+            let (label, time) = match self.time_limit {
+                Some(limit) => ("Time left", limit.saturating_sub(elapsed)),
+                None => ("Time", elapsed),
+            };
+            let secs = time.as_secs();
+            lines.push(format!("{label}: {}:{:02}", secs / 60, secs % 60));
+        }
+        lines.push(self.status.clone());
+        if let Some(row) = self.typed_row {
+            lines.push(format!("Row {}, now type a column", row + 1));
+        }
+        lines.push(
+            "Arrows: slide into the hole   row+column (e.g. 24): click   r: rematch   q: quit"
+                .to_string(),
+        );
+
+        for (i, line) in lines.into_iter().enumerate() {
+            queue!(out, MoveTo(2, 5 + size + i as u16), Print(line))?;
+        }
+        out.flush()
+    }
+}
+
+/// Columns taken by a tile, which is two blocks wide to look about square, and a gap.
+const TILE_WIDTH: u16 = 3;
+
+fn draw_tiles(out: &mut impl Write, x: u16, y: u16, tiles: &BoardTiles) -> io::Result<()> {
+    for (i, row) in tiles.iter().enumerate() {
+        queue!(out, MoveTo(x, y + i as u16))?;
+        for tile in row {
+            match tile {
+                Some(color) => queue!(out, SetForegroundColor(term_color(*color)), Print("██ "))?,
+                None => queue!(out, ResetColor, Print("   "))?,
+            }
+        }
+    }
+    queue!(out, ResetColor)
+}
+
+fn term_color(color: Color) -> TermColor {
+    match color {
+        Color::White => TermColor::White,
+        Color::Yellow => TermColor::Yellow,
+        Color::Orange => TermColor::Rgb {
+            r: 255,
+            g: 165,
+            b: 0,
+        },
+        Color::Red => TermColor::Red,
+        Color::Green => TermColor::Green,
+        Color::Blue => TermColor::Blue,
+        Color::Purple => TermColor::Rgb {
+            r: 128,
+            g: 0,
+            b: 128,
+        },
+        Color::Pink => TermColor::Rgb {
+            r: 255,
+            g: 105,
+            b: 180,
+        },
+    }
+}
@@ -0,0 +1,209 @@
+#![cfg(feature = "ssr")]
+
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{
+    handlers::Connection,
+    solver::{heuristic, solve, valid_clicks, SolverOptions},
+    types::{BoardInner, Capabilities, ClientMessage, ServerMessage, Target},
+};
+use leptos::log;
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+use tokio::{
+    select,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task,
+    time::{sleep_until, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    /// Mostly greedy, with plenty of random clicks
+    Easy,
+    /// Always clicks whatever looks closest to the target
+    Medium,
+    /// Follows a plan from the solver
+    Hard,
+}
+
+impl std::str::FromStr for Difficulty {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard];
+
+    /// The name bots of this difficulty play under, which people can't take.
+    pub fn bot_name(self) -> String {
+        format!("Computer ({self:?})")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BotConfig {
+    pub difficulty: Difficulty,
+    /// Average time between clicks
+    pub move_delay: Duration,
+}
+
+impl BotConfig {
+    pub fn new(difficulty: Difficulty) -> Self {
+        let move_delay = match difficulty {
+            Difficulty::Easy => Duration::from_millis(1500),
+            Difficulty::Medium => Duration::from_millis(1000),
+            Difficulty::Hard => Duration::from_millis(800),
+        };
+
+        BotConfig {
+            difficulty,
+            move_delay,
+        }
+    }
+}
+
+/// Solver settings that stay quick enough to not hold up the first click.
+const SOLVER_OPTIONS: SolverOptions = SolverOptions {
+    weight: 6,
+    max_nodes: 100_000,
+};
+
+/// Starts a bot, returning its side of the connection for `game_loop`.
+pub(crate) fn spawn_bot(config: BotConfig) -> Connection {
+    let (msg_tx, msg_rx) = mpsc::unbounded_channel();
+    let (client_tx, client_rx) = mpsc::unbounded_channel();
+
+    let name = config.difficulty.bot_name();
+    tokio::spawn(bot_loop(config, msg_rx, client_tx));
+
+    Connection {
+        msg_tx,
+        msg_rx: client_rx,
+        name: Some(name),
+        capabilities: Capabilities::ALL,
+        is_bot: true,
+        key: None,
+    }
+}
+
+async fn bot_loop(
+    config: BotConfig,
+    mut msg_rx: UnboundedReceiver<ServerMessage>,
+    client_tx: UnboundedSender<ClientMessage>,
+) {
+    log!("Entering bot loop ({:?})", config.difficulty);
+
+    let mut game: Option<(Target, BoardInner)> = None;
+    let mut plan = VecDeque::new();
+    let mut last_hole = None;
+    let mut next_move = Instant::now();
+
+    loop {
+        select! {
+            msg = msg_rx.recv() => {
+                let Some(msg) = msg else { break; };
+                match msg {
+                    ServerMessage::GameStart(start) | ServerMessage::Resync(start) => {
+                        game = Some((start.target, start.board));
+                        plan.clear();
+                        last_hole = None;
+                        next_move = Instant::now() + move_delay(&config);
+                    }
+                    // the plan was made for a board the server doesn't have
+                    ServerMessage::MoveRejected { board, .. } => {
+                        if let Some((_, current)) = &mut game {
+                            *current = board;
+                            plan.clear();
+                            last_hole = None;
+                        }
+                    }
+                    // the next round of a series comes with a new game start
+                    ServerMessage::GameEnd { .. } => game = None,
+                    // bots never turn down a rematch
+                    ServerMessage::RematchRequested => {
+                        if client_tx.send(ClientMessage::Rematch).is_err() {
+                            break;
+                        }
+                    }
+                    ServerMessage::OpponentLeft => break,
+                    _ => {}
+                }
+            }
+            _ = sleep_until(next_move), if game.is_some() => {
+                let (target, board) = game.as_mut().expect("checked by select");
+
+                if config.difficulty == Difficulty::Hard && plan.is_empty() {
+                    let (board, target) = (board.clone(), target.clone());
+                    let solution = task::spawn_blocking(move || solve(&board, &target, &SOLVER_OPTIONS))
+                        .await
+                        .expect("solver panicked");
+                    match solution {
+                        Ok(solution) => plan.extend(solution.clicks),
+                        Err(unsolved) => log!("Bot solver gave up: {:?}", unsolved.stats),
+                    }
+                }
+
+                let pos = match config.difficulty {
+                    Difficulty::Easy if rand::thread_rng().gen_bool(1.0 / 3.0) => random_click(board),
+                    Difficulty::Hard => plan.pop_front().unwrap_or_else(|| greedy_click(board, target, last_hole)),
+                    _ => greedy_click(board, target, last_hole),
+                };
+
+                last_hole = Some(board.hole);
+                board.click(pos);
+                if client_tx.send(ClientMessage::Click { pos }).is_err() {
+                    break;
+                }
+                next_move = Instant::now() + move_delay(&config);
+            }
+        }
+    }
+
+    log!("Exiting bot loop");
+}
+
+/// Varies the delay a bit so the bot doesn't click like clockwork.
+fn move_delay(config: &BotConfig) -> Duration {
+    config
+        .move_delay
+        .mul_f64(rand::thread_rng().gen_range(0.75..1.25))
+}
+
+fn random_click(board: &BoardInner) -> (usize, usize) {
+    let clicks: Vec<_> = valid_clicks(board).collect();
+    *clicks
+        .choose(&mut rand::thread_rng())
+        .expect("there is always a valid click")
+}
+
+/// The click that looks closest to the target, without undoing the last one.
+fn greedy_click(
+    board: &BoardInner,
+    target: &Target,
+    last_hole: Option<(usize, usize)>,
+) -> (usize, usize) {
+    let mut clicks: Vec<_> = valid_clicks(board)
+        .filter(|&pos| Some(pos) != last_hole)
+        .collect();
+    // shuffle so that ties don't always go the same way
+    clicks.shuffle(&mut rand::thread_rng());
+
+    clicks
+        .into_iter()
+        .min_by_key(|&pos| {
+            let mut next = board.clone();
+            next.click(pos);
+            heuristic(&next, target)
+        })
+        .expect("there is always a valid click")
+}
@@ -14,12 +14,21 @@ use serde_json::json;
 pub enum AppError {
     #[error("Not Found")]
     NotFound,
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Bad Request: {0}")]
+    BadRequest(String),
+    #[error("Internal Server Error")]
+    InternalError,
 }
 
 impl AppError {
     pub fn status_code(&self) -> StatusCode {
         match self {
             AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
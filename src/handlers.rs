@@ -1,142 +1,808 @@
 #![cfg(feature = "ssr")]
 
 use crate::{
+    bot::Difficulty,
     error_template::AppError,
-    types::{BoardInner, BoardTiles, ClientMessage, Color, GameStart, ServerMessage, Target},
+    history::{self, MatchSummary, PlayerResult},
+    matchmaker::{
+        normalize_room_code, GameUpdate, JoinKind, JoinRequest, MatchOptions, MovePolicy,
+        MAX_BEST_OF,
+    },
+    rating::INITIAL_RATING,
+    replay::{self, Replay, ReplayClick},
+    solver::heuristic,
+    types::{
+        parse_tile_counts, BoardInner, BoardSize, BoardTiles, Capabilities, ClientMessage, Color,
+        GameId, GameStart, Hello, MoveError, Outcome, Palette, RatingChange, Series, ServerMessage,
+        SpectateStart, Target, MAX_NAME_LEN, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    },
 };
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        Extension, WebSocketUpgrade,
+        Extension, Path, Query, WebSocketUpgrade,
     },
-    response::Response,
+    http::header,
+    response::{IntoResponse, Response},
 };
 use futures::StreamExt;
 use leptos::log;
-use rand::{distributions::Standard, prelude::Distribution};
-use rand::{seq::SliceRandom, Rng};
-use strum::EnumCount;
+use rand::seq::SliceRandom;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tokio::{
     select,
-    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::{interval, sleep_until, timeout, Instant},
 };
 
-enum GameEvent {
+/// How long a disconnected player has to reconnect before they forfeit.
+const RECONNECT_GRACE: Duration = Duration::from_secs(30);
+/// How long a new connection has to send its `Hello`, and then `ClientMessage::Join`.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long players get to look at the result of a round before the next one starts
+const ROUND_BREAK: Duration = Duration::from_secs(5);
+/// How long players have to agree on a rematch once a series is over
+const REMATCH_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often players are sent the time, to keep their clocks from drifting
+const CLOCK_INTERVAL: Duration = Duration::from_secs(5);
+/// Longest time limit that can be picked for a round
+const MAX_TIME_LIMIT: Duration = Duration::from_secs(30 * 60);
+/// Shortest and longest player keys, which are letters and digits
+const MIN_KEY_LEN: usize = 16;
+const MAX_KEY_LEN: usize = 64;
+
+pub(crate) enum GameEvent {
     Message { id: usize, msg: ClientMessage },
     Disconnected { id: usize },
+    Reconnected { id: usize, conn: Connection },
+    Spectate { conn: Connection },
+}
+
+/// A connected player, as seen from the server.
+///
+/// The websocket itself is owned by `ws_loop`; once it exits, both channels are closed.
+/// Bots stand in for the websocket with their own task.
+pub(crate) struct Connection {
+    pub(crate) msg_tx: UnboundedSender<ServerMessage>,
+    pub(crate) msg_rx: UnboundedReceiver<ClientMessage>,
+    /// Name shown on the leaderboard, players without one stay anonymous
+    pub(crate) name: Option<String>,
+    /// Agreed on in the handshake, messages the client can't handle aren't sent
+    pub(crate) capabilities: Capabilities,
+    /// Played by a program, either ours or one connected to `/bot/connect`
+    pub(crate) is_bot: bool,
+    /// Secret the player's rating is kept under, which unlike the name nobody else knows
+    pub(crate) key: Option<String>,
+}
+
+impl Connection {
+    fn spawn(ws: WebSocket, format: WireFormat, capabilities: Capabilities) -> Self {
+        let (msg_tx, msg_rx) = mpsc::unbounded_channel();
+        let (client_tx, client_rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(ws_loop(ws, format, client_tx, msg_rx));
+
+        Connection {
+            msg_tx,
+            msg_rx: client_rx,
+            name: None,
+            capabilities,
+            is_bot: false,
+            key: None,
+        }
+    }
+
+    pub(crate) fn send(&self, msg: ServerMessage) {
+        _ = self.msg_tx.send(msg);
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.msg_tx.is_closed()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ConnectParams {
+    room: Option<String>,
+    #[serde(default)]
+    create_room: bool,
+    session: Option<String>,
+    spectate: Option<GameId>,
+    #[serde(default)]
+    practice: bool,
+    bot: Option<Difficulty>,
+    best_of: Option<u32>,
+    #[serde(default)]
+    fair: bool,
+    /// In seconds
+    time_limit: Option<u64>,
+    /// Written as `5x3`, see [`BoardSize`]
+    size: Option<String>,
+    /// Number of colors, sharing the tiles evenly
+    colors: Option<usize>,
+    /// Tiles of each color written as `4,4,4,3`, overriding `colors`
+    tiles: Option<String>,
+    /// Only for `/bot/connect`, see [`BotTokens`]
+    token: Option<String>,
+    /// Kept secret by the player, see [`Connection::key`]
+    key: Option<String>,
+}
+
+/// Names of the programs allowed to play on `/bot/connect`, by their token.
+#[derive(Debug, Default, Clone)]
+pub struct BotTokens(HashMap<String, String>);
+
+impl BotTokens {
+    /// Reads `BOT_TOKENS`, written as `name:token,name:token`.
+    pub fn from_env() -> Self {
+        let tokens = std::env::var("BOT_TOKENS").unwrap_or_default();
+        let tokens = tokens
+            .split(',')
+            .filter_map(|entry| entry.split_once(':'))
+            .filter_map(|(name, token)| {
+                let name = normalize_name(name)?;
+                let token = token.trim();
+                (!token.is_empty()).then(|| (token.to_string(), name))
+            })
+            .collect();
+        BotTokens(tokens)
+    }
+
+    fn name(&self, token: &str) -> Option<&str> {
+        self.0.get(token).map(String::as_str)
+    }
+
+    /// Whether a name belongs to a bot, whether one of these or a built-in one, so people
+    /// can't share its place on the leaderboard.
+    fn is_reserved(&self, name: &str) -> bool {
+        let bot_names = Difficulty::ALL.map(Difficulty::bot_name);
+        self.0
+            .values()
+            .chain(&bot_names)
+            .any(|bot_name| bot_name.eq_ignore_ascii_case(name))
+    }
 }
 
 pub async fn connect(
-    Extension(ws_tx): Extension<UnboundedSender<WebSocket>>,
+    Extension(join_tx): Extension<UnboundedSender<JoinRequest>>,
+    Extension(bot_tokens): Extension<Arc<BotTokens>>,
+    Query(params): Query<ConnectParams>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    join(join_tx, bot_tokens, params, ws, None)
+}
+
+/// Like [`connect`], for programs playing under the name that goes with their token. Their
+/// opponents are told they're playing a bot.
+pub async fn bot_connect(
+    Extension(join_tx): Extension<UnboundedSender<JoinRequest>>,
+    Extension(bot_tokens): Extension<Arc<BotTokens>>,
+    Query(params): Query<ConnectParams>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let name = params
+        .token
+        .as_deref()
+        .and_then(|token| bot_tokens.name(token))
+        .ok_or(AppError::Unauthorized)?
+        .to_string();
+    log!("Bot {name} is connecting");
+    join(join_tx, bot_tokens, params, ws, Some(name))
+}
+
+/// Joins the matchmaker once the connection has sent its `Join`, as a bot with the given name
+/// if it has one. People asking for a bot's name play without one.
+///
+/// Board options that can't be played are turned down rather than replaced by the defaults.
+fn join(
+    join_tx: UnboundedSender<JoinRequest>,
+    bot_tokens: Arc<BotTokens>,
+    params: ConnectParams,
     ws: WebSocketUpgrade,
+    bot_name: Option<String>,
 ) -> Result<Response, AppError> {
-    Ok(ws.on_upgrade(|ws| async move {
-        _ = ws_tx.send(ws);
+    let best_of = params
+        .best_of
+        .filter(|&best_of| best_of % 2 == 1 && best_of <= MAX_BEST_OF);
+    let size = match params.size.as_deref() {
+        Some(size) => size
+            .parse()
+            .map_err(|_| AppError::BadRequest(format!("{size} is not a board size")))?,
+        None => BoardSize::default(),
+    };
+    let tiles = params.tiles.as_deref().filter(|tiles| !tiles.is_empty());
+    let palette = match (tiles, params.colors) {
+        (Some(tiles), _) => parse_tile_counts(tiles)
+            .and_then(|counts| Palette::custom(&counts, size))
+            .ok_or_else(|| {
+                AppError::BadRequest(format!("tiles {tiles} don't fill a {size} board"))
+            })?,
+        (None, Some(colors)) => Palette::even(colors, size).ok_or_else(|| {
+            AppError::BadRequest(format!("{colors} colors don't fit a {size} board"))
+        })?,
+        (None, None) => Palette::standard(size),
+    };
+    let options = MatchOptions {
+        best_of: best_of.unwrap_or(1),
+        fair: params.fair,
+        time_limit: params
+            .time_limit
+            .map(Duration::from_secs)
+            .filter(|&limit| !limit.is_zero() && limit <= MAX_TIME_LIMIT),
+        size,
+        palette,
+        ..MatchOptions::default()
+    };
+    let key = params.key.as_deref().and_then(normalize_key);
+    let mut kind = match params {
+        ConnectParams {
+            session: Some(session),
+            ..
+        } => JoinKind::Resume(session),
+        ConnectParams {
+            spectate: Some(game_id),
+            ..
+        } => JoinKind::Spectate(game_id),
+        ConnectParams { practice: true, .. } => JoinKind::Practice(size, palette),
+        ConnectParams {
+            bot: Some(difficulty),
+            ..
+        } => JoinKind::Bot(difficulty, options),
+        ConnectParams {
+            create_room: true, ..
+        } => JoinKind::CreateRoom(options),
+        ConnectParams {
+            room: Some(code), ..
+        } => JoinKind::JoinRoom(normalize_room_code(&code)),
+        _ => JoinKind::Public {
+            rating: INITIAL_RATING,
+        },
+    };
+
+    Ok(ws.on_upgrade(|mut ws| async move {
+        let Ok(Some((format, capabilities))) = timeout(JOIN_TIMEOUT, handshake(&mut ws)).await
+        else {
+            log!("Connection did not finish the handshake");
+            return;
+        };
+        let mut conn = Connection::spawn(ws, format, capabilities);
+        match timeout(JOIN_TIMEOUT, conn.msg_rx.recv()).await {
+            Ok(Some(ClientMessage::Join { name })) => {
+                conn.is_bot = bot_name.is_some();
+                conn.key = key.filter(|_| !conn.is_bot);
+                conn.name = bot_name.or_else(|| {
+                    name.as_deref()
+                        .and_then(normalize_name)
+                        .filter(|name| !bot_tokens.is_reserved(name))
+                });
+            }
+            _ => {
+                log!("Connection did not join");
+                return;
+            }
+        }
+        // only the public queue pairs players by rating
+        if let (JoinKind::Public { rating }, Some(key)) = (&mut kind, &conn.key) {
+            *rating = history::load_rating(key.clone()).await;
+        }
+        _ = join_tx.send(JoinRequest { conn, kind });
     }))
 }
 
-pub async fn lobby_loop(mut ws_rx: UnboundedReceiver<WebSocket>) {
-    loop {
-        wait_for_players(&mut ws_rx).await;
+/// How a connection encodes its messages, picked by the kind of frame its [`Hello`] comes in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WireFormat {
+    /// Binary frames, used by the web client
+    Bincode,
+    /// Text frames, for clients not written in Rust, see `public/protocol.schema.json`
+    Json,
+}
+
+impl WireFormat {
+    fn encode(self, msg: &impl Serialize) -> Message {
+        match self {
+            WireFormat::Bincode => {
+                Message::Binary(bincode::serialize(msg).expect("failed to serialize"))
+            }
+            WireFormat::Json => {
+                Message::Text(serde_json::to_string(msg).expect("failed to serialize"))
+            }
+        }
+    }
+
+    /// Reads a message, which has to be in this format.
+    fn decode<T: DeserializeOwned>(self, msg: &Message) -> Option<T> {
+        match (self, msg) {
+            (WireFormat::Bincode, Message::Binary(msg)) => bincode::deserialize(msg).ok(),
+            (WireFormat::Json, Message::Text(msg)) => serde_json::from_str(msg).ok(),
+            _ => None,
+        }
     }
 }
 
-async fn wait_for_players(ws_rx: &mut UnboundedReceiver<WebSocket>) {
-    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<GameEvent>();
-    let mut msg_txs: [Option<UnboundedSender<ServerMessage>>; 2] = std::array::from_fn(|_| None);
-    let mut free_ids = vec![0, 1];
+/// Reads the client's [`Hello`] and answers it, returning the format of the connection and
+/// the capabilities both sides have.
+///
+/// Clients from before the handshake send a `Join` instead, and can't read the answer either.
+async fn handshake(ws: &mut WebSocket) -> Option<(WireFormat, Capabilities)> {
+    let msg = ws.next().await?.ok()?;
+    let format = match msg {
+        Message::Text(_) => WireFormat::Json,
+        _ => WireFormat::Bincode,
+    };
+    // such a `Join` can read as version 0
+    let hello = format
+        .decode::<Hello>(&msg)
+        .filter(|hello| hello.version > 0)?;
 
-    log!("Waiting for players");
+    // clients know they were accepted by getting their own version back, and otherwise learn
+    // which version they should have
+    let accepted = (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&hello.version);
+    let capabilities = hello.capabilities.intersection(Capabilities::ALL);
+    let answer = Hello {
+        version: if accepted {
+            hello.version
+        } else {
+            PROTOCOL_VERSION
+        },
+        capabilities,
+    };
+    ws.send(format.encode(&answer)).await.ok()?;
 
-    loop {
-        select! {
-            event = event_rx.recv() => {
-                let event = event.expect("event_rx stopped, but event_tx shouldn't be dropped");
-                let id = match event {
-                    GameEvent::Message { id, msg: ClientMessage::Ping } => {
-                        log!("Received ping from {id}");
-                        continue;
-                    }
-                    GameEvent::Message { id, msg: _ } => {
-                        log!("Unexpected message from id {id}");
-                        id
-                    }
-                    GameEvent::Disconnected { id } => {
-                        id
-                    }
-                };
-                log!("Freeing id {id}");
-                msg_txs[id] = None;
-                free_ids.push(id);
-            }
-            ws = ws_rx.recv() => {
-                let Some(ws) = ws else { log!("ws_rx stopped"); break; };
-                let id = free_ids.pop().expect("no ids left");
-                let (msg_tx, msg_rx) = mpsc::unbounded_channel();
+    if !accepted {
+        log!(
+            "Turning away client with protocol version {}",
+            hello.version
+        );
+        return None;
+    }
+    Some((format, capabilities))
+}
 
-                log!("Assigning id {id}");
+/// Trims and shortens a display name, which is left out if nothing remains.
+fn normalize_name(name: &str) -> Option<String> {
+    let name: String = name
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect();
+    let name = name.trim_end();
 
-                tokio::spawn(ws_loop(id, ws, event_tx.clone(), msg_rx));
-                msg_txs[id] = Some(msg_tx);
+    (!name.is_empty()).then(|| name.to_string())
+}
 
-                if !free_ids.is_empty() {
-                    continue;
-                }
+/// Keeps a player key if it's long enough not to be guessed.
+fn normalize_key(key: &str) -> Option<String> {
+    let valid = (MIN_KEY_LEN..=MAX_KEY_LEN).contains(&key.len())
+        && key.chars().all(|c| c.is_ascii_alphanumeric());
+    valid.then(|| key.to_string())
+}
 
-                let full_msg_txs = msg_txs.map(|tx| tx.expect("msg_tx is None"));
+/// What a game is waiting for between clicks.
+enum Phase {
+    Playing,
+    /// The next round of the series starts at this time
+    Break {
+        until: Instant,
+    },
+    /// The series is over, and these players have asked for a rematch
+    Rematch {
+        until: Instant,
+        requested: [bool; 2],
+    },
+}
 
-                log!("Starting new game");
-                tokio::spawn(game_loop(event_rx, full_msg_txs));
-                return;
-            }
+impl Phase {
+    /// When the phase is over, which for a round is when its time runs out.
+    fn deadline(&self, round: &Round, options: &MatchOptions) -> Option<Instant> {
+        match self {
+            Phase::Playing => options.time_limit.map(|limit| round.started + limit),
+            Phase::Break { until } | Phase::Rematch { until, .. } => Some(*until),
         }
     }
 }
 
-async fn game_loop(
-    mut event_rx: UnboundedReceiver<GameEvent>,
-    mut msg_txs: [UnboundedSender<ServerMessage>; 2],
-) {
-    log!("Entering game loop");
+/// The players of a game, as the history knows them.
+struct Roster {
+    names: [Option<String>; 2],
+    /// Both players' keys, set only when the game is rated
+    keys: Option<[String; 2]>,
+}
 
-    let target = generate_target();
-    let mut boards = [Board::generate(), Board::generate()];
+/// One game of a series, with its own id, replay and place in the history.
+struct Round {
+    game_id: GameId,
+    target: Target,
+    boards: [Board; 2],
+    moves: [u32; 2],
+    /// Clicks of each player that were rejected
+    rejected: [u32; 2],
+    started: Instant,
+    replay: Replay,
+    /// The `GameEnd` each player was sent, kept for those who reconnect before the next round
+    results: Option<[ServerMessage; 2]>,
+}
+
+impl Round {
+    fn new(game_id: GameId, options: &MatchOptions) -> Self {
+        let target = generate_target(options.size, &options.palette);
+        let board = Board::generate(options.size, &options.palette);
+        let boards = if options.fair {
+            [Board(board.0.clone()), board]
+        } else {
+            [board, Board::generate(options.size, &options.palette)]
+        };
+        let replay = Replay::new(
+            game_id,
+            unix_time_ms(),
+            target.clone(),
+            boards.each_ref().map(|board| board.0.clone()),
+        );
 
-    for (id, tx) in msg_txs.iter_mut().enumerate() {
-        _ = tx.send(ServerMessage::GameStart(GameStart {
+        Round {
+            game_id,
             target,
-            board: boards[id].0,
-            opponent_board: boards[1 - id].0,
-        }));
+            boards,
+            moves: [0; 2],
+            rejected: [0; 2],
+            started: Instant::now(),
+            replay,
+            results: None,
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
     }
 
-    while let Some(event) = event_rx.recv().await {
+    /// The player whose board is closest to the target, unless they're equally close.
+    fn closest_to_target(&self) -> Option<usize> {
+        let [first, second] = self
+            .boards
+            .each_ref()
+            .map(|board| heuristic(&board.0, &self.target));
+        match first.cmp(&second) {
+            Ordering::Less => Some(0),
+            Ordering::Greater => Some(1),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// Saves the replay and the result, returning the new ratings if the round was rated.
+    async fn finish(&self, roster: &Roster) -> Option<[RatingChange; 2]> {
+        let summary = MatchSummary {
+            game_id: self.game_id,
+            started_at: self.replay.started_at,
+            duration_ms: self.elapsed_ms(),
+            players: [0, 1].map(|id| PlayerResult {
+                name: roster.names[id].clone(),
+                moves: self.moves[id],
+            }),
+            winner: self.replay.winner,
+        };
+        let ((), rating_changes) = tokio::join!(
+            replay::save(self.replay.clone()),
+            history::record(summary, roster.keys.clone())
+        );
+        rating_changes
+    }
+}
+
+/// Records a finished round and tells everyone how it went, returning what comes next.
+async fn end_round(
+    round: &mut Round,
+    series: &mut Series,
+    winner: Option<usize>,
+    time_up: bool,
+    roster: &Roster,
+    msg_txs: &[UnboundedSender<ServerMessage>; 2],
+    spectators: &mut Vec<UnboundedSender<ServerMessage>>,
+) -> Phase {
+    // players are told once the ratings are updated
+    round.replay.winner = winner;
+    if let Some(winner) = winner {
+        series.wins[winner] += 1;
+    }
+    let rating_changes = round.finish(roster).await;
+
+    let results = [0, 1].map(|id| {
+        let outcome = match winner {
+            Some(winner) if winner == id => Outcome::Win,
+            Some(_) => Outcome::Loss,
+            None => Outcome::Draw,
+        };
+        ServerMessage::GameEnd {
+            outcome,
+            time_up,
+            rating: rating_changes.map(|changes| changes[id]),
+            series: series.for_player(id),
+        }
+    });
+    for (tx, result) in msg_txs.iter().zip(&results) {
+        _ = tx.send(result.clone());
+    }
+    round.results = Some(results);
+    broadcast(
+        spectators,
+        ServerMessage::GameOver {
+            winner,
+            series: *series,
+        },
+    );
+
+    match series.winner() {
+        Some(_) => Phase::Rematch {
+            until: Instant::now() + REMATCH_TIMEOUT,
+            requested: [false; 2],
+        },
+        None => Phase::Break {
+            until: Instant::now() + ROUND_BREAK,
+        },
+    }
+}
+
+/// Asks the matchmaker for the id of the next round.
+async fn next_game_id(updates: &UnboundedSender<GameUpdate>, previous: GameId) -> Option<GameId> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    updates
+        .send(GameUpdate::NextRound {
+            previous,
+            reply: reply_tx,
+        })
+        .ok()?;
+    reply_rx.await.ok()
+}
+
+/// Runs every round of a series between two players, and any rematches after it.
+///
+/// Returns the id of the last round, which is the id the game is known by at the end.
+pub(crate) async fn game_loop(
+    game_id: GameId,
+    players: [Connection; 2],
+    sessions: [String; 2],
+    options: MatchOptions,
+    event_tx: UnboundedSender<GameEvent>,
+    mut event_rx: UnboundedReceiver<GameEvent>,
+    updates: UnboundedSender<GameUpdate>,
+) -> GameId {
+    log!("Entering game loop for game {game_id}");
+
+    let keys = match players.each_ref().map(|player| player.key.clone()) {
+        [Some(first), Some(second)] if options.rated => Some([first, second]),
+        _ => None,
+    };
+    let roster = Roster {
+        names: players.each_ref().map(|player| player.name.clone()),
+        keys,
+    };
+    let bots = players.each_ref().map(|player| player.is_bot);
+    let mut capabilities = players.each_ref().map(|player| player.capabilities);
+    let mut next_id = 0;
+    let mut msg_txs = players.map(|Connection { msg_tx, msg_rx, .. }| {
+        tokio::spawn(forward_events(next_id, msg_rx, event_tx.clone()));
+        next_id += 1;
+        msg_tx
+    });
+    let mut disconnected_at: [Option<Instant>; 2] = [None, None];
+    let mut spectators: Vec<UnboundedSender<ServerMessage>> = Vec::new();
+
+    let mut round = Round::new(game_id, &options);
+    let mut series = Series::new(options.best_of);
+    let mut phase = Phase::Playing;
+
+    let snapshot = |round: &Round, series: &Series, id: usize| GameStart {
+        game_id: round.game_id,
+        target: round.target.clone(),
+        board: round.boards[id].0.clone(),
+        opponent_board: round.boards[1 - id].0.clone(),
+        session: sessions[id].clone(),
+        opponent_name: roster.names[1 - id].clone(),
+        opponent_is_bot: bots[1 - id],
+        series: series.for_player(id),
+        moves: [round.moves[id], round.moves[1 - id]],
+        elapsed_ms: round.elapsed_ms(),
+        time_limit_ms: options.time_limit.map(|limit| limit.as_millis() as u64),
+    };
+    let spectate_start = |round: &Round, series: &Series| SpectateStart {
+        game_id: round.game_id,
+        target: round.target.clone(),
+        boards: round.boards.each_ref().map(|board| board.0.clone()),
+        names: roster.names.clone(),
+        series: *series,
+    };
+
+    for (id, tx) in msg_txs.iter().enumerate() {
+        _ = tx.send(ServerMessage::GameStart(snapshot(&round, &series, id)));
+    }
+
+    let mut clock = interval(CLOCK_INTERVAL);
+    loop {
+        let reconnect_deadline = disconnected_at
+            .iter()
+            .flatten()
+            .min()
+            .map(|&at| at + RECONNECT_GRACE);
+        let phase_deadline = phase.deadline(&round, &options);
+
+        let event = select! {
+            event = event_rx.recv() => {
+                // game_loop holds a sender, so this never stops
+                let Some(event) = event else { break; };
+                event
+            }
+            _ = sleep_until(reconnect_deadline.unwrap_or_else(Instant::now)), if reconnect_deadline.is_some() => {
+                let id = (0..2)
+                    .find(|&id| disconnected_at[id].is_some_and(|at| at + RECONNECT_GRACE <= Instant::now()))
+                    .expect("deadline passed but no player timed out");
+                log!("Player {id} did not reconnect in time");
+                // the round is lost by forfeit, and recorded and rated like any other
+                if let Phase::Playing = phase {
+                    end_round(&mut round, &mut series, Some(1 - id), false, &roster, &msg_txs, &mut spectators).await;
+                }
+                _ = msg_txs[1 - id].send(ServerMessage::OpponentLeft);
+                break;
+            }
+            _ = sleep_until(phase_deadline.unwrap_or_else(Instant::now)), if phase_deadline.is_some() => {
+                match phase {
+                    Phase::Playing => {
+                        let winner = round.closest_to_target();
+                        log!("Time is up for game {}, winner {winner:?}", round.game_id);
+                        phase = end_round(&mut round, &mut series, winner, true, &roster, &msg_txs, &mut spectators).await;
+                        continue;
+                    }
+                    Phase::Rematch { .. } => {
+                        log!("No rematch for game {}", round.game_id);
+                        break;
+                    }
+                    Phase::Break { .. } => {}
+                }
+
+                let Some(game_id) = next_game_id(&updates, round.game_id).await else { break; };
+                round = Round::new(game_id, &options);
+                phase = Phase::Playing;
+                for (id, tx) in msg_txs.iter().enumerate() {
+                    _ = tx.send(ServerMessage::GameStart(snapshot(&round, &series, id)));
+                }
+                broadcast(&mut spectators, ServerMessage::SpectateStart(spectate_start(&round, &series)));
+                continue;
+            }
+            _ = clock.tick(), if matches!(phase, Phase::Playing) => {
+                let elapsed_ms = round.elapsed_ms();
+                for (tx, capabilities) in msg_txs.iter().zip(&capabilities) {
+                    if capabilities.contains(Capabilities::CLOCK) {
+                        _ = tx.send(ServerMessage::Clock { elapsed_ms });
+                    }
+                }
+                continue;
+            }
+        };
+
         match event {
             GameEvent::Message {
                 id,
                 msg: ClientMessage::Click { pos },
             } => {
-                if pos.0 >= 5 || pos.1 >= 5 {
-                    log!("Out of bounds click position: {:?}", pos);
-                    break;
+                if !matches!(phase, Phase::Playing) {
+                    log!("Ignoring click from {id} between rounds");
+                    continue;
                 }
-                let updated = boards[id].click_tile(pos);
-                if !updated {
-                    log!("Click position did not move tile: {:?}", pos);
-                    break;
+                if let Err(reason) = round.boards[id].click_tile(pos) {
+                    log!("Rejected click from {id} at {pos:?}: {reason}");
+                    round.rejected[id] += 1;
+                    // players who can't be told aren't punished either
+                    let policy = if capabilities[id].contains(Capabilities::MOVE_REJECTED) {
+                        options.move_policy
+                    } else {
+                        MovePolicy::Ignore
+                    };
+                    let strikes_left = match policy {
+                        // their board is likely out of step with ours, so it's sent either way
+                        MovePolicy::Ignore => {
+                            _ = msg_txs[id]
+                                .send(ServerMessage::Resync(snapshot(&round, &series, id)));
+                            continue;
+                        }
+                        MovePolicy::Warn => None,
+                        MovePolicy::Forfeit(limit) => {
+                            Some(limit.saturating_sub(round.rejected[id]))
+                        }
+                    };
+                    _ = msg_txs[id].send(ServerMessage::MoveRejected {
+                        pos,
+                        reason,
+                        board: round.boards[id].0.clone(),
+                        strikes_left,
+                    });
+
+                    if strikes_left == Some(0) {
+                        log!("Player {id} forfeits the round");
+                        phase = end_round(
+                            &mut round,
+                            &mut series,
+                            Some(1 - id),
+                            false,
+                            &roster,
+                            &msg_txs,
+                            &mut spectators,
+                        )
+                        .await;
+                    }
+                    continue;
                 }
 
+                let time_ms = round.elapsed_ms();
+                round.moves[id] += 1;
+                round.replay.clicks.push(ReplayClick {
+                    player: id,
+                    pos,
+                    time_ms,
+                });
+
                 let other_id = 1 - id;
-                _ = msg_txs[other_id].send(ServerMessage::OpponentClick { pos });
+                _ = msg_txs[other_id].send(ServerMessage::OpponentClick { pos, time_ms });
+                broadcast(
+                    &mut spectators,
+                    ServerMessage::PlayerClick {
+                        player: id,
+                        pos,
+                        time_ms,
+                    },
+                );
 
-                if !boards[id].matches_target(&target) {
+                if !round.boards[id].matches_target(&round.target) {
                     continue;
                 }
 
-                // win handling
-                _ = msg_txs[id].send(ServerMessage::GameEnd { is_win: true });
-                _ = msg_txs[other_id].send(ServerMessage::GameEnd { is_win: false });
-                break;
+                phase = end_round(
+                    &mut round,
+                    &mut series,
+                    Some(id),
+                    false,
+                    &roster,
+                    &msg_txs,
+                    &mut spectators,
+                )
+                .await;
+            }
+            GameEvent::Message {
+                id,
+                msg: ClientMessage::Rematch,
+            } => {
+                let Phase::Rematch { requested, .. } = &mut phase else {
+                    log!("Ignoring rematch from {id} before the series is over");
+                    continue;
+                };
+                requested[id] = true;
+                if !requested[1 - id] {
+                    log!("Player {id} asked for a rematch");
+                    _ = msg_txs[1 - id].send(ServerMessage::RematchRequested);
+                    continue;
+                }
+
+                log!("Starting rematch");
+                let Some(game_id) = next_game_id(&updates, round.game_id).await else {
+                    break;
+                };
+                round = Round::new(game_id, &options);
+                series = Series::new(options.best_of);
+                phase = Phase::Playing;
+                for (id, tx) in msg_txs.iter().enumerate() {
+                    _ = tx.send(ServerMessage::GameStart(snapshot(&round, &series, id)));
+                }
+                broadcast(
+                    &mut spectators,
+                    ServerMessage::SpectateStart(spectate_start(&round, &series)),
+                );
             }
             GameEvent::Message {
                 id,
@@ -144,48 +810,243 @@ async fn game_loop(
             } => {
                 log!("Received ping from {id}")
             }
+            GameEvent::Message {
+                id,
+                msg: ClientMessage::Join { .. },
+            } => {
+                log!("Ignoring repeated join from {id}")
+            }
             GameEvent::Disconnected { id } => {
-                let other_id = 1 - id;
-                _ = msg_txs[other_id].send(ServerMessage::OpponentLeft);
-                break;
+                // the old connection can drop after the player has already reconnected
+                if !msg_txs[id].is_closed() {
+                    continue;
+                }
+                // once the series is over there's nothing to come back to
+                if let Phase::Rematch { .. } = phase {
+                    log!("Player {id} left after the series");
+                    _ = msg_txs[1 - id].send(ServerMessage::OpponentLeft);
+                    break;
+                }
+                log!("Player {id} disconnected, waiting for them to reconnect");
+                disconnected_at[id] = Some(Instant::now());
+                _ = msg_txs[1 - id].send(ServerMessage::OpponentDisconnected);
+            }
+            GameEvent::Reconnected {
+                id,
+                conn:
+                    Connection {
+                        msg_tx,
+                        msg_rx,
+                        capabilities: new_capabilities,
+                        ..
+                    },
+            } => {
+                log!("Player {id} reconnected");
+                tokio::spawn(forward_events(id, msg_rx, event_tx.clone()));
+                msg_txs[id] = msg_tx;
+                capabilities[id] = new_capabilities;
+
+                _ = msg_txs[id].send(ServerMessage::Resync(snapshot(&round, &series, id)));
+                // the round may have ended while they were away
+                if let Some(results) = &round.results {
+                    _ = msg_txs[id].send(results[id].clone());
+                }
+                if let Phase::Rematch { requested, .. } = phase {
+                    if requested[1 - id] {
+                        _ = msg_txs[id].send(ServerMessage::RematchRequested);
+                    }
+                }
+                if disconnected_at[id].take().is_some() {
+                    _ = msg_txs[1 - id].send(ServerMessage::OpponentReconnected);
+                }
+            }
+            GameEvent::Spectate {
+                conn: Connection { msg_tx, msg_rx, .. },
+            } => {
+                log!("Adding spectator, {} watching", spectators.len() + 1);
+                tokio::spawn(ignore_spectator_messages(msg_rx));
+                _ = msg_tx.send(ServerMessage::SpectateStart(spectate_start(
+                    &round, &series,
+                )));
+                spectators.push(msg_tx);
             }
         }
     }
 
-    log!("Exiting game loop");
+    log!("Exiting game loop for game {}", round.game_id);
+    round.game_id
 }
 
-fn generate_target() -> Target {
-    let mut target: Target = Default::default();
+fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_millis() as u64
+}
 
-    'retry: loop {
-        let mut counts = [0; Color::COUNT];
+#[derive(Deserialize)]
+pub struct ReplayParams {
+    #[serde(default)]
+    format: ReplayFormat,
+}
 
-        for row in &mut target {
-            for slot in row {
-                let color = rand::thread_rng().gen::<Color>();
-                let count = &mut counts[color as usize];
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ReplayFormat {
+    #[default]
+    Json,
+    Bincode,
+}
 
-                // too many of same color
-                *count += 1;
-                if *count > 4 {
-                    continue 'retry;
-                }
+pub async fn get_replay(
+    Path(game_id): Path<GameId>,
+    Query(params): Query<ReplayParams>,
+) -> Result<Response, AppError> {
+    let replay = replay::load(game_id).await.ok_or(AppError::NotFound)?;
+
+    let response = match params.format {
+        ReplayFormat::Json => (
+            [(header::CONTENT_TYPE, "application/json")],
+            replay.to_json(),
+        )
+            .into_response(),
+        ReplayFormat::Bincode => (
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            replay.to_bincode(),
+        )
+            .into_response(),
+    };
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    #[serde(default = "default_history_players")]
+    players: u32,
+    #[serde(default = "default_history_matches")]
+    matches: u32,
+}
+
+fn default_history_players() -> u32 {
+    10
+}
+
+fn default_history_matches() -> u32 {
+    20
+}
+
+/// Top players and recent matches as JSON.
+pub async fn get_history(Query(params): Query<HistoryParams>) -> Result<Response, AppError> {
+    // keeps a single request from reading the whole database
+    let players = params.players.min(100);
+    let matches = params.matches.min(100);
+    let history = history::load(players, matches)
+        .await
+        .ok_or(AppError::InternalError)?;
+
+    Ok(axum::Json(history).into_response())
+}
+
+/// Single player game against the clock.
+pub(crate) async fn practice_loop(
+    Connection {
+        msg_tx,
+        mut msg_rx,
+        capabilities,
+        ..
+    }: Connection,
+    size: BoardSize,
+    palette: Palette,
+) {
+    log!("Entering practice loop");
+
+    let target = generate_target(size, &palette);
+    let mut board = Board::generate(size, &palette);
+    let mut moves = 0;
+
+    _ = msg_tx.send(ServerMessage::PracticeStart {
+        target: target.clone(),
+        board: board.0.clone(),
+    });
+    let start = Instant::now();
+
+    while let Some(msg) = msg_rx.recv().await {
+        let ClientMessage::Click { pos } = msg else {
+            continue;
+        };
 
-                *slot = color;
+        // nothing is at stake, so practice always warns
+        if let Err(reason) = board.click_tile(pos) {
+            log!("Rejected practice click at {pos:?}: {reason}");
+            if !capabilities.contains(Capabilities::MOVE_REJECTED) {
+                continue;
             }
+            _ = msg_tx.send(ServerMessage::MoveRejected {
+                pos,
+                reason,
+                board: board.0.clone(),
+                strikes_left: None,
+            });
+            continue;
         }
+        moves += 1;
 
-        break;
+        if board.matches_target(&target) {
+            _ = msg_tx.send(ServerMessage::PracticeEnd {
+                moves,
+                time_ms: start.elapsed().as_millis() as u64,
+            });
+            break;
+        }
     }
 
-    target
+    log!("Exiting practice loop");
 }
 
-async fn ws_loop(
+/// Sends a message to every spectator, forgetting those who left.
+fn broadcast(spectators: &mut Vec<UnboundedSender<ServerMessage>>, msg: ServerMessage) {
+    spectators.retain(|tx| tx.send(msg.clone()).is_ok());
+}
+
+/// Spectators can't play, but their connection is kept open as long as they keep reading.
+async fn ignore_spectator_messages(mut msg_rx: UnboundedReceiver<ClientMessage>) {
+    while let Some(msg) = msg_rx.recv().await {
+        if let ClientMessage::Click { .. } = msg {
+            log!("Ignoring click from spectator");
+        }
+    }
+}
+
+/// Tags messages from one player's connection with their id for `game_loop`.
+async fn forward_events(
     id: usize,
-    mut ws: WebSocket,
+    mut msg_rx: UnboundedReceiver<ClientMessage>,
     event_tx: UnboundedSender<GameEvent>,
+) {
+    while let Some(msg) = msg_rx.recv().await {
+        if event_tx.send(GameEvent::Message { id, msg }).is_err() {
+            return;
+        }
+    }
+    _ = event_tx.send(GameEvent::Disconnected { id });
+}
+
+/// Draws the target from the board's tiles, so it never needs more of a color than there is.
+fn generate_target(size: BoardSize, palette: &Palette) -> Target {
+    let mut colors = palette.tile_colors();
+    colors.shuffle(&mut rand::thread_rng());
+
+    colors
+        .chunks(size.target)
+        .take(size.target)
+        .map(<[Color]>::to_vec)
+        .collect()
+}
+
+async fn ws_loop(
+    mut ws: WebSocket,
+    format: WireFormat,
+    client_tx: UnboundedSender<ClientMessage>,
     mut msg_rx: UnboundedReceiver<ServerMessage>,
 ) {
     log!("Entering ws_loop");
@@ -193,80 +1054,63 @@ async fn ws_loop(
         select! {
             msg = ws.next() => {
                 let Some(Ok(msg)) = msg else { break; };
-                let msg = match msg {
-                    Message::Binary(msg) => msg,
+                match msg {
+                    Message::Binary(_) | Message::Text(_) => {}
                     Message::Ping(_) => continue,
                     _ => break,
-                };
-                let Ok(msg) = bincode::deserialize(&msg) else {
+                }
+                let Some(msg) = format.decode(&msg) else {
                     log!("got invalid message");
                     break;
                 };
-                if event_tx.send(GameEvent::Message { id, msg }).is_err() {
+                if client_tx.send(msg).is_err() {
                     break;
                 }
             }
             msg = msg_rx.recv() => {
                 let Some(msg) = msg else { break; };
-                let msg = bincode::serialize(&msg).expect("failed to serialize");
-                if let Err(e) = ws.send(Message::Binary(msg)).await {
+                if let Err(e) = ws.send(format.encode(&msg)).await {
                     log!("Error when sending message: {e}");
                     break;
                 }
             }
         }
     }
-    _ = event_tx.send(GameEvent::Disconnected { id });
     log!("Exiting ws_loop");
 }
 
-impl Distribution<Color> for Standard {
-    fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> Color {
-        rng.gen_range(0..Color::COUNT).into()
-    }
-}
-
 struct Board(BoardInner);
 
 impl Board {
-    fn generate() -> Self {
-        let mut colors: [Color; 24] = std::array::from_fn(|i| (i / 4).into());
+    fn generate(size: BoardSize, palette: &Palette) -> Self {
+        let mut colors = palette.tile_colors();
         colors.shuffle(&mut rand::thread_rng());
 
         let mut colors = colors.into_iter();
-        let mut tiles = BoardTiles::default();
+        let mut tiles: BoardTiles = vec![vec![None; size.board]; size.board];
+        // the hole starts in the middle, just off it on even boards
+        let hole = (size.board / 2, size.board / 2);
 
         for (i, row) in tiles.iter_mut().enumerate() {
             for (j, slot) in row.iter_mut().enumerate() {
-                // we will always leave the center tile empty
-                if i == 2 && j == 2 {
-                    continue;
+                if (i, j) != hole {
+                    *slot = Some(colors.next().unwrap());
                 }
-
-                *slot = Some(colors.next().unwrap());
             }
         }
 
-        Board(BoardInner {
-            tiles,
-            hole: (2, 2),
-        })
+        Board(BoardInner { tiles, hole })
     }
 
-    fn click_tile(&mut self, pos: (usize, usize)) -> bool {
-        use crate::utils::slide;
-
-        let BoardInner { tiles, hole } = &mut self.0;
-        let update = |old: (usize, usize), new: (usize, usize)| {
-            tiles[new.0][new.1] = tiles[old.0][old.1];
-        };
-
-        if !slide(pos, *hole, update) {
-            return false;
+    fn click_tile(&mut self, pos: (usize, usize)) -> Result<(), MoveError> {
+        let size = self.0.tiles.len();
+        if pos.0 >= size || pos.1 >= size {
+            return Err(MoveError::OutOfBounds);
         }
-
-        *hole = pos;
-        true
+        if !self.0.click(pos) {
+            return Err(MoveError::NoTileMoved);
+        }
+        Ok(())
     }
 
     fn matches_target(&self, target: &Target) -> bool {
@@ -0,0 +1,349 @@
+//! Results of finished games and player ratings, kept in a SQLite database.
+//!
+//! Players are grouped by name on the leaderboard, so only named players are ranked.
+//! Games are still recorded when nobody has a name and show up in the recent matches.
+//! Only games from the public queue between two named people with a winner change ratings.
+//!
+//! Names can be picked by anyone, so ratings are kept under a secret key each player joins with.
+//! A name's rating belongs to the first key rated under it, and games where someone plays under
+//! a name that belongs to another key aren't rated.
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::GameId;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchSummary {
+    pub game_id: GameId,
+    /// Unix time in milliseconds
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub players: [PlayerResult; 2],
+    pub winner: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerResult {
+    pub name: Option<String>,
+    pub moves: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub name: String,
+    pub rating: Option<i32>,
+    pub games: u32,
+    pub wins: u32,
+    /// Duration of the fastest game this player won
+    pub fastest_win_ms: Option<u64>,
+}
+
+/// Everything on the leaderboard page, as served at `/history`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct History {
+    /// Ordered by wins, then by fewest games played
+    pub top_players: Vec<PlayerStats>,
+    /// Newest first
+    pub recent_matches: Vec<MatchSummary>,
+}
+
+cfg_if::cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::{
+        rating::{updated_ratings, INITIAL_RATING},
+        types::RatingChange,
+    };
+    use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+    /// Where the database is kept, set by `HISTORY_DB`.
+    fn history_path() -> String {
+        std::env::var("HISTORY_DB").unwrap_or_else(|_| "history.db".to_string())
+    }
+
+    fn open() -> rusqlite::Result<Connection> {
+        let conn = Connection::open(history_path())?;
+        create_tables(&conn)?;
+        Ok(conn)
+    }
+
+    fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS matches (
+                game_id INTEGER PRIMARY KEY,
+                started_at INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                winner INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS match_players (
+                game_id INTEGER NOT NULL REFERENCES matches (game_id),
+                player INTEGER NOT NULL,
+                name TEXT,
+                moves INTEGER NOT NULL,
+                PRIMARY KEY (game_id, player)
+            );
+            CREATE INDEX IF NOT EXISTS match_players_name ON match_players (name);
+            -- ratings used to be kept by name alone, which let anyone change them
+            DROP TABLE IF EXISTS ratings;
+            CREATE TABLE IF NOT EXISTS player_ratings (
+                key TEXT PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                rating REAL NOT NULL
+            );",
+        )
+    }
+
+    fn rating(conn: &Connection, key: &str) -> rusqlite::Result<f64> {
+        let rating = conn
+            .query_row("SELECT rating FROM player_ratings WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(rating.unwrap_or(INITIAL_RATING))
+    }
+
+    /// Whether `name` is free, or already belongs to `key`.
+    fn may_use_name(conn: &Connection, key: &str, name: &str) -> rusqlite::Result<bool> {
+        let owner: Option<String> = conn
+            .query_row("SELECT key FROM player_ratings WHERE name = ?1", [name], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(owner.map_or(true, |owner| owner == key))
+    }
+
+    /// Updates both players' ratings, unless one of them plays under someone else's name.
+    fn update_ratings(
+        tx: &Transaction,
+        players: [(&str, &str); 2],
+        winner: usize,
+    ) -> rusqlite::Result<Option<[RatingChange; 2]>> {
+        for (key, name) in players {
+            if !may_use_name(tx, key, name)? {
+                return Ok(None);
+            }
+        }
+        let [(first, _), (second, _)] = players;
+        let old = [rating(tx, first)?, rating(tx, second)?];
+        let new = updated_ratings(old, winner);
+
+        for ((key, name), rating) in players.iter().zip(new) {
+            // players who change their name take their rating with them
+            tx.execute(
+                "INSERT INTO player_ratings (key, name, rating) VALUES (?1, ?2, ?3)
+                ON CONFLICT (key) DO UPDATE SET name = excluded.name, rating = excluded.rating",
+                params![key, name, rating],
+            )?;
+        }
+
+        Ok(Some(std::array::from_fn(|id| RatingChange {
+            rating: new[id].round() as i32,
+            delta: new[id].round() as i32 - old[id].round() as i32,
+        })))
+    }
+
+    // sqlite integers are signed, so game ids are stored with their bits reinterpreted
+    fn insert(
+        conn: &mut Connection,
+        summary: &MatchSummary,
+        keys: Option<[String; 2]>,
+    ) -> rusqlite::Result<Option<[RatingChange; 2]>> {
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO matches (game_id, started_at, duration_ms, winner) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                summary.game_id as i64,
+                summary.started_at,
+                summary.duration_ms,
+                summary.winner,
+            ],
+        )?;
+        for (player, result) in summary.players.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO match_players (game_id, player, name, moves) VALUES (?1, ?2, ?3, ?4)",
+                params![summary.game_id as i64, player, result.name, result.moves],
+            )?;
+        }
+
+        let [first, second] = summary.players.each_ref().map(|player| player.name.as_deref());
+        let rating_changes = match (first, second, summary.winner, &keys) {
+            (Some(first), Some(second), Some(winner), Some([first_key, second_key]))
+                if first != second && first_key != second_key =>
+            {
+                update_ratings(&tx, [(first_key, first), (second_key, second)], winner)?
+            }
+            _ => None,
+        };
+
+        tx.commit()?;
+        Ok(rating_changes)
+    }
+
+    fn query(conn: &Connection, players: u32, matches: u32) -> rusqlite::Result<History> {
+        let top_players = conn
+            .prepare(
+                "SELECT p.name, r.rating, COUNT(*), SUM(m.winner IS p.player),
+                    MIN(CASE WHEN m.winner IS p.player THEN m.duration_ms END)
+                FROM match_players p
+                    JOIN matches m USING (game_id)
+                    LEFT JOIN player_ratings r USING (name)
+                WHERE p.name IS NOT NULL
+                GROUP BY p.name
+                ORDER BY 4 DESC, 3 ASC
+                LIMIT ?1",
+            )?
+            .query_map([players], |row| {
+                Ok(PlayerStats {
+                    name: row.get(0)?,
+                    rating: row.get::<_, Option<f64>>(1)?.map(|rating| rating.round() as i32),
+                    games: row.get(2)?,
+                    wins: row.get(3)?,
+                    fastest_win_ms: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut player_stmt =
+            conn.prepare("SELECT name, moves FROM match_players WHERE game_id = ?1 AND player = ?2")?;
+        let mut load_player = |game_id: i64, player: usize| {
+            player_stmt.query_row(params![game_id, player], |row| {
+                Ok(PlayerResult {
+                    name: row.get(0)?,
+                    moves: row.get(1)?,
+                })
+            })
+        };
+        let recent_matches = conn
+            .prepare(
+                "SELECT game_id, started_at, duration_ms, winner FROM matches
+                ORDER BY started_at DESC
+                LIMIT ?1",
+            )?
+            .query_map([matches], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .map(|row| {
+                let (game_id, started_at, duration_ms, winner) = row?;
+                Ok(MatchSummary {
+                    game_id: game_id as GameId,
+                    started_at,
+                    duration_ms,
+                    players: [load_player(game_id, 0)?, load_player(game_id, 1)?],
+                    winner,
+                })
+            })
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(History {
+            top_players,
+            recent_matches,
+        })
+    }
+
+    /// Saves a finished game, returning both players' new ratings if it was rated.
+    ///
+    /// Only games given both players' keys are rated.
+    pub(crate) async fn record(
+        summary: MatchSummary,
+        keys: Option<[String; 2]>,
+    ) -> Option<[RatingChange; 2]> {
+        let game_id = summary.game_id;
+        let result = tokio::task::spawn_blocking(move || insert(&mut open()?, &summary, keys))
+            .await
+            .expect("recording game panicked");
+
+        match result {
+            Ok(rating_changes) => rating_changes,
+            Err(e) => {
+                leptos::log!("Failed to record game {game_id}: {e}");
+                None
+            }
+        }
+    }
+
+    /// The current rating kept under a player's key, which starts at [`INITIAL_RATING`].
+    pub(crate) async fn load_rating(key: String) -> f64 {
+        let result = tokio::task::spawn_blocking(move || rating(&open()?, &key))
+            .await
+            .expect("loading rating panicked");
+
+        result.unwrap_or_else(|e| {
+            leptos::log!("Failed to load rating: {e}");
+            INITIAL_RATING
+        })
+    }
+
+    /// The top `players` and the last `matches` games.
+    pub(crate) async fn load(players: u32, matches: u32) -> Option<History> {
+        let result = tokio::task::spawn_blocking(move || query(&open()?, players, matches))
+            .await
+            .expect("loading history panicked");
+
+        match result {
+            Ok(history) => Some(history),
+            Err(e) => {
+                leptos::log!("Failed to load history: {e}");
+                None
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn summary(game_id: GameId, names: [&str; 2], winner: usize) -> MatchSummary {
+            MatchSummary {
+                game_id,
+                started_at: 0,
+                duration_ms: 1000,
+                players: names.map(|name| PlayerResult {
+                    name: Some(name.to_string()),
+                    moves: 10,
+                }),
+                winner: Some(winner),
+            }
+        }
+
+        fn keys(first: &str, second: &str) -> Option<[String; 2]> {
+            Some([first.to_string(), second.to_string()])
+        }
+
+        fn database() -> Connection {
+            let conn = Connection::open_in_memory().unwrap();
+            create_tables(&conn).unwrap();
+            conn
+        }
+
+        #[test]
+        fn games_without_keys_are_not_rated() {
+            let mut conn = database();
+            let changes = insert(&mut conn, &summary(1, ["alice", "bob"], 0), None).unwrap();
+            assert!(changes.is_none());
+        }
+
+        #[test]
+        fn names_belong_to_the_first_key_rated_under_them() {
+            let mut conn = database();
+            let games = [["alice", "bob"], ["alice", "bob"], ["alicia", "bob"]];
+
+            let changes = insert(&mut conn, &summary(1, games[0], 0), keys("alice1234", "bob12345"))
+                .unwrap()
+                .expect("rated");
+            assert_eq!(changes.map(|change| change.delta), [16, -16]);
+
+            // someone else playing as alice can't change her rating, or bob's
+            let changes =
+                insert(&mut conn, &summary(2, games[1], 1), keys("mallory1", "bob12345")).unwrap();
+            assert!(changes.is_none());
+            assert_eq!(rating(&conn, "alice1234").unwrap(), INITIAL_RATING + 16.0);
+            assert_eq!(rating(&conn, "mallory1").unwrap(), INITIAL_RATING);
+
+            // while alice keeps her rating under a new name
+            let changes = insert(&mut conn, &summary(3, games[2], 0), keys("alice1234", "bob12345"))
+                .unwrap()
+                .expect("rated");
+            assert!(changes[0].rating > INITIAL_RATING as i32 + 16);
+            // which frees the old one
+            assert!(may_use_name(&conn, "mallory1", "alice").unwrap());
+        }
+    }
+}}
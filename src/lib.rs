@@ -1,9 +1,15 @@
 use cfg_if::cfg_if;
 pub mod app;
+pub mod bot;
 pub mod error_template;
 pub mod fileserv;
 pub mod handlers;
-mod types;
+pub mod history;
+pub mod matchmaker;
+pub mod rating;
+pub mod replay;
+pub mod solver;
+pub mod types;
 mod utils;
 
 cfg_if! { if #[cfg(feature = "hydrate")] {
@@ -26,16 +26,25 @@ async fn main() {
     let addr = leptos_options.site_addr;
     let routes = generate_route_list(|| view! { <App/> }).await;
 
-    let (ws_tx, ws_rx) = mpsc::unbounded_channel();
-    tokio::spawn(start_axum::handlers::lobby_loop(ws_rx));
+    let (join_tx, join_rx) = mpsc::unbounded_channel();
+    tokio::spawn(start_axum::matchmaker::matchmaker_loop(
+        join_rx,
+        start_axum::matchmaker::MatchmakerConfig::from_env(),
+    ));
 
     // build our application with a route
     let app = Router::new()
         .route("/api/*fn_name", post(leptos_axum::handle_server_fns))
         .route("/connect", get(start_axum::handlers::connect))
+        .route("/bot/connect", get(start_axum::handlers::bot_connect))
+        .route("/replays/:id", get(start_axum::handlers::get_replay))
+        .route("/history", get(start_axum::handlers::get_history))
         .leptos_routes(&leptos_options, routes, || view! { <App/> })
         .fallback(file_and_error_handler)
-        .layer(Extension(ws_tx))
+        .layer(Extension(join_tx))
+        .layer(Extension(Arc::new(
+            start_axum::handlers::BotTokens::from_env(),
+        )))
         .layer(Extension(Arc::new(leptos_options.clone())))
         .with_state(leptos_options);
 
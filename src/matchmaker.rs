@@ -0,0 +1,530 @@
+#![cfg(feature = "ssr")]
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use crate::{
+    bot::{spawn_bot, BotConfig, Difficulty},
+    handlers::{game_loop, practice_loop, Connection, GameEvent},
+    types::{BoardSize, GameId, Palette, ServerMessage},
+};
+use leptos::log;
+use rand::seq::SliceRandom;
+use tokio::{
+    select,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::{interval, sleep_until, Instant},
+};
+
+pub struct JoinRequest {
+    pub(crate) conn: Connection,
+    pub(crate) kind: JoinKind,
+}
+
+pub(crate) enum JoinKind {
+    Public { rating: f64 },
+    CreateRoom(MatchOptions),
+    JoinRoom(String),
+    Resume(String),
+    Spectate(GameId),
+    Practice(BoardSize, Palette),
+    Bot(Difficulty, MatchOptions),
+}
+
+/// Longest series that can be picked
+pub const MAX_BEST_OF: u32 = 7;
+
+/// Settings picked by whoever sets up a private room or a game against a bot.
+///
+/// Games from the public queue use the defaults, apart from being rated.
+#[derive(Debug, Clone, Copy)]
+pub struct MatchOptions {
+    /// Rounds in a series, whoever wins a majority of them wins the series
+    pub best_of: u32,
+    /// Both players start every round from the same board
+    pub fair: bool,
+    /// When a round runs out of time the player closest to the target wins it, a tie is replayed
+    pub time_limit: Option<Duration>,
+    /// Sides of the board and the target of every round
+    pub size: BoardSize,
+    /// Tiles of each color on every board, which must fill a board of `size`
+    pub palette: Palette,
+    /// Set by the matchmaker from its config, players don't pick it
+    pub move_policy: MovePolicy,
+    /// Set by the matchmaker for public games between people, the only ones that change ratings
+    pub rated: bool,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions {
+            best_of: 1,
+            fair: false,
+            time_limit: None,
+            size: BoardSize::default(),
+            palette: Palette::standard(BoardSize::default()),
+            move_policy: MovePolicy::default(),
+            rated: false,
+        }
+    }
+}
+
+/// Sent by running games to keep the matchmaker up to date.
+pub(crate) enum GameUpdate {
+    /// The next round of a series needs a new game id, which is sent back on `reply`
+    NextRound {
+        previous: GameId,
+        reply: oneshot::Sender<GameId>,
+    },
+    Ended(GameId),
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MatchmakerConfig {
+    /// Play against a bot after waiting this long in the public queue
+    pub bot_fallback: Option<(Duration, Difficulty)>,
+    /// What happens to players whose clicks don't move a tile
+    pub move_policy: MovePolicy,
+}
+
+/// How a game treats clicks it can't apply, which stale or broken clients send.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MovePolicy {
+    /// Don't tell the player, but resync them with the board as it is
+    Ignore,
+    /// Tell the player and send them the board as it is
+    #[default]
+    Warn,
+    /// Warn, and the player loses the round once this many of their clicks were rejected
+    Forfeit(u32),
+}
+
+/// Written as `ignore`, `warn` or `forfeit:<clicks>`.
+impl std::str::FromStr for MovePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "ignore" => Ok(MovePolicy::Ignore),
+            None if s == "warn" => Ok(MovePolicy::Warn),
+            Some(("forfeit", limit)) => match limit.parse() {
+                Ok(limit) if limit > 0 => Ok(MovePolicy::Forfeit(limit)),
+                _ => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+impl MatchmakerConfig {
+    /// Reads `BOT_FALLBACK_SECS` and `BOT_FALLBACK_DIFFICULTY`, leaving the fallback off
+    /// unless the former is set, and `INVALID_MOVE_POLICY`.
+    pub fn from_env() -> Self {
+        let after = std::env::var("BOT_FALLBACK_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs);
+        let difficulty = std::env::var("BOT_FALLBACK_DIFFICULTY")
+            .ok()
+            .and_then(|difficulty| difficulty.parse().ok())
+            .unwrap_or(Difficulty::Medium);
+        let move_policy = std::env::var("INVALID_MOVE_POLICY")
+            .ok()
+            .and_then(|policy| policy.parse().ok())
+            .unwrap_or_default();
+
+        MatchmakerConfig {
+            bot_fallback: after.map(|after| (after, difficulty)),
+            move_policy,
+        }
+    }
+}
+
+/// Keeps track of everyone waiting for a game and every game in progress.
+struct Matchmaker {
+    config: MatchmakerConfig,
+    /// Players waiting for a public game, longest waiting first
+    queue: VecDeque<Waiting>,
+    rooms: HashMap<String, (Connection, MatchOptions)>,
+    games: HashMap<GameId, GameHandle>,
+    /// Maps session tokens to the game and player id they resume
+    sessions: HashMap<String, (GameId, usize)>,
+    update_tx: UnboundedSender<GameUpdate>,
+}
+
+struct Waiting {
+    conn: Connection,
+    since: Instant,
+    rating: f64,
+}
+
+/// Widest rating gap between two players who just started waiting
+const RATING_GAP: f64 = 100.0;
+/// How much the accepted rating gap widens for every second spent waiting
+const RATING_GAP_GROWTH: f64 = 20.0;
+/// How often the queue is checked for pairs that have come within range
+const QUEUE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Waiting {
+    fn max_rating_gap(&self, now: Instant) -> f64 {
+        RATING_GAP + RATING_GAP_GROWTH * now.duration_since(self.since).as_secs_f64()
+    }
+}
+
+/// The places in the queue of the next two players to be paired, the first before the second.
+///
+/// Longer waiting players are matched first, each with the closest rated opponent in range.
+/// A pair is in range if the gap is small enough for either of them.
+fn next_pair(queue: &VecDeque<Waiting>, now: Instant) -> Option<(usize, usize)> {
+    queue.iter().enumerate().find_map(|(i, player)| {
+        let gap = |other: &Waiting| (other.rating - player.rating).abs();
+        queue
+            .iter()
+            .enumerate()
+            .skip(i + 1)
+            .filter(|(_, other)| {
+                gap(other) <= player.max_rating_gap(now).max(other.max_rating_gap(now))
+            })
+            .min_by(|(_, a), (_, b)| gap(a).total_cmp(&gap(b)))
+            .map(|(j, _)| (i, j))
+    })
+}
+
+struct GameHandle {
+    event_tx: UnboundedSender<GameEvent>,
+    sessions: [String; 2],
+}
+
+pub async fn matchmaker_loop(
+    mut join_rx: UnboundedReceiver<JoinRequest>,
+    config: MatchmakerConfig,
+) {
+    let (update_tx, mut update_rx) = mpsc::unbounded_channel();
+    let mut matchmaker = Matchmaker {
+        config,
+        queue: VecDeque::new(),
+        rooms: HashMap::new(),
+        games: HashMap::new(),
+        sessions: HashMap::new(),
+        update_tx,
+    };
+
+    log!("Waiting for players");
+
+    let mut queue_check = interval(QUEUE_CHECK_INTERVAL);
+    loop {
+        let fallback_at = matchmaker.bot_fallback_at();
+
+        select! {
+            request = join_rx.recv() => {
+                let Some(request) = request else { log!("join_rx stopped"); break; };
+                matchmaker.handle_join(request);
+            }
+            _ = sleep_until(fallback_at.unwrap_or_else(Instant::now)), if fallback_at.is_some() => {
+                matchmaker.start_bot_fallback();
+            }
+            _ = queue_check.tick() => {
+                matchmaker.match_queue();
+            }
+            update = update_rx.recv() => {
+                // the matchmaker holds a sender, so this never stops
+                let Some(update) = update else { break; };
+                match update {
+                    GameUpdate::NextRound { previous, reply } => {
+                        matchmaker.next_round(previous, reply);
+                    }
+                    GameUpdate::Ended(game_id) => {
+                        matchmaker.end_game(game_id);
+                        log!("Game {game_id} ended, {} still running", matchmaker.games.len());
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Matchmaker {
+    fn handle_join(&mut self, JoinRequest { conn, kind }: JoinRequest) {
+        // players who left while waiting don't get to play
+        self.rooms.retain(|_, (host, _)| !host.is_closed());
+        self.queue.retain(|waiting| !waiting.conn.is_closed());
+
+        match kind {
+            JoinKind::Public { rating } => {
+                self.queue.push_back(Waiting {
+                    conn,
+                    since: Instant::now(),
+                    rating,
+                });
+                log!("{} players in public queue", self.queue.len());
+                self.match_queue();
+            }
+            JoinKind::CreateRoom(options) => {
+                let code = self.generate_room_code();
+                log!("Creating room {code}");
+                conn.send(ServerMessage::RoomCreated { code: code.clone() });
+                self.rooms.insert(code, (conn, options));
+            }
+            JoinKind::JoinRoom(code) => match self.rooms.remove(&code) {
+                Some((host, options)) => {
+                    log!("Joining room {code}");
+                    self.start_game([host, conn], options);
+                }
+                None => {
+                    log!("Room {code} not found");
+                    conn.send(ServerMessage::RoomNotFound);
+                }
+            },
+            JoinKind::Resume(session) => self.resume(session, conn),
+            JoinKind::Spectate(game_id) => self.spectate(game_id, conn),
+            JoinKind::Practice(size, palette) => {
+                log!("Starting {size} practice game");
+                tokio::spawn(practice_loop(conn, size, palette));
+            }
+            JoinKind::Bot(difficulty, options) => {
+                log!("Starting game against {difficulty:?} bot");
+                self.start_game([conn, spawn_bot(BotConfig::new(difficulty))], options);
+            }
+        }
+    }
+
+    /// Pairs up players in the public queue whose ratings are close enough.
+    fn match_queue(&mut self) {
+        // players who left while waiting would be paired and lose by forfeit
+        self.queue.retain(|waiting| !waiting.conn.is_closed());
+        let now = Instant::now();
+
+        while let Some((i, j)) = next_pair(&self.queue, now) {
+            // j comes after i, so removing it first leaves i in place
+            let opponent = self.queue.remove(j).expect("opponent is in the queue");
+            let player = self.queue.remove(i).expect("player is in the queue");
+            log!(
+                "Pairing players rated {:.0} and {:.0}",
+                player.rating,
+                opponent.rating
+            );
+            // programs on /bot/connect can queue too, but only games between people are rated
+            let options = MatchOptions {
+                rated: !player.conn.is_bot && !opponent.conn.is_bot,
+                ..MatchOptions::default()
+            };
+            self.start_game([player.conn, opponent.conn], options);
+        }
+    }
+
+    /// When the longest waiting player should get a bot, if the fallback is on.
+    fn bot_fallback_at(&self) -> Option<Instant> {
+        let (after, _) = self.config.bot_fallback?;
+        let waiting = self.queue.front()?;
+        Some(waiting.since + after)
+    }
+
+    fn start_bot_fallback(&mut self) {
+        let Some((after, difficulty)) = self.config.bot_fallback else {
+            return;
+        };
+
+        while let Some(waiting) = self.queue.pop_front() {
+            if waiting.since + after > Instant::now() {
+                self.queue.push_front(waiting);
+                break;
+            }
+            if waiting.conn.is_closed() {
+                continue;
+            }
+
+            log!("No opponent found, starting game against {difficulty:?} bot");
+            self.start_game(
+                [waiting.conn, spawn_bot(BotConfig::new(difficulty))],
+                MatchOptions::default(),
+            );
+        }
+    }
+
+    fn start_game(&mut self, players: [Connection; 2], mut options: MatchOptions) {
+        let game_id = self.generate_game_id();
+        let sessions: [String; 2] = std::array::from_fn(|_| generate_session_token());
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        for (id, session) in sessions.iter().enumerate() {
+            self.sessions.insert(session.clone(), (game_id, id));
+        }
+        self.games.insert(
+            game_id,
+            GameHandle {
+                event_tx: event_tx.clone(),
+                sessions: sessions.clone(),
+            },
+        );
+        log!("Starting game {game_id}, {} running", self.games.len());
+
+        options.move_policy = self.config.move_policy;
+        let update_tx = self.update_tx.clone();
+        tokio::spawn(async move {
+            // the game id changes with every round of a series
+            let game_id = game_loop(
+                game_id,
+                players,
+                sessions,
+                options,
+                event_tx,
+                event_rx,
+                update_tx.clone(),
+            )
+            .await;
+            _ = update_tx.send(GameUpdate::Ended(game_id));
+        });
+    }
+
+    fn generate_game_id(&self) -> GameId {
+        loop {
+            let game_id = rand::random();
+            if !self.games.contains_key(&game_id) {
+                return game_id;
+            }
+        }
+    }
+
+    /// Moves a game to a new id for its next round, so each round gets its own replay.
+    fn next_round(&mut self, previous: GameId, reply: oneshot::Sender<GameId>) {
+        let Some(handle) = self.games.remove(&previous) else {
+            return;
+        };
+
+        let game_id = self.generate_game_id();
+        for session in &handle.sessions {
+            if let Some(entry) = self.sessions.get_mut(session) {
+                entry.0 = game_id;
+            }
+        }
+        self.games.insert(game_id, handle);
+        log!("Game {previous} continues as {game_id}");
+
+        _ = reply.send(game_id);
+    }
+
+    fn end_game(&mut self, game_id: GameId) {
+        let Some(handle) = self.games.remove(&game_id) else {
+            return;
+        };
+        for session in &handle.sessions {
+            self.sessions.remove(session);
+        }
+    }
+
+    fn resume(&mut self, session: String, conn: Connection) {
+        let Some(&(game_id, id)) = self.sessions.get(&session) else {
+            log!("Unknown session");
+            conn.send(ServerMessage::SessionExpired);
+            return;
+        };
+
+        log!("Resuming game {game_id} for player {id}");
+        let handle = &self.games[&game_id];
+        if let Err(mpsc::error::SendError(GameEvent::Reconnected { conn, .. })) =
+            handle.event_tx.send(GameEvent::Reconnected { id, conn })
+        {
+            // the game ended, but we haven't been told yet
+            conn.send(ServerMessage::SessionExpired);
+        }
+    }
+
+    fn spectate(&mut self, game_id: GameId, conn: Connection) {
+        let Some(handle) = self.games.get(&game_id) else {
+            log!("Game {game_id} not found for spectating");
+            conn.send(ServerMessage::GameNotFound);
+            return;
+        };
+
+        log!("Spectating game {game_id}");
+        if let Err(mpsc::error::SendError(GameEvent::Spectate { conn })) =
+            handle.event_tx.send(GameEvent::Spectate { conn })
+        {
+            conn.send(ServerMessage::GameNotFound);
+        }
+    }
+
+    fn generate_room_code(&self) -> String {
+        loop {
+            let code: String = (0..ROOM_CODE_LEN)
+                .map(|_| *ROOM_CODE_CHARS.choose(&mut rand::thread_rng()).unwrap() as char)
+                .collect();
+
+            if !self.rooms.contains_key(&code) {
+                return code;
+            }
+        }
+    }
+}
+
+// ambiguous characters like I/1 and O/0 are left out
+const ROOM_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const ROOM_CODE_LEN: usize = 5;
+
+fn generate_session_token() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+pub(crate) fn normalize_room_code(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Capabilities;
+
+    /// The first pair among players given by their rating and how many seconds they've waited.
+    fn pair(players: &[(f64, u64)]) -> Option<(usize, usize)> {
+        let now = Instant::now();
+        let queue = players
+            .iter()
+            .map(|&(rating, waited)| {
+                let (msg_tx, _) = mpsc::unbounded_channel();
+                let (_, msg_rx) = mpsc::unbounded_channel();
+                Waiting {
+                    conn: Connection {
+                        msg_tx,
+                        msg_rx,
+                        name: None,
+                        capabilities: Capabilities::ALL,
+                        is_bot: false,
+                        key: None,
+                    },
+                    since: now - Duration::from_secs(waited),
+                    rating,
+                }
+            })
+            .collect();
+        next_pair(&queue, now)
+    }
+
+    #[test]
+    fn pairs_close_ratings_right_away() {
+        assert_eq!(pair(&[(1500.0, 0), (1600.0, 0)]), Some((0, 1)));
+        assert_eq!(pair(&[(1500.0, 0), (1601.0, 0)]), None);
+        assert_eq!(pair(&[(1500.0, 0)]), None);
+    }
+
+    #[test]
+    fn longest_waiting_player_gets_the_closest_opponent() {
+        let players = [(1500.0, 3), (1590.0, 2), (1800.0, 1), (1480.0, 0)];
+        assert_eq!(pair(&players), Some((0, 3)));
+        // players nobody is close to are passed over
+        assert_eq!(pair(&[(1000.0, 3), (1800.0, 2), (1850.0, 1)]), Some((1, 2)));
+    }
+
+    #[test]
+    fn rating_gap_widens_while_waiting() {
+        // 200 apart, which is in range once either of them has waited 5 seconds
+        assert_eq!(pair(&[(1500.0, 5), (1700.0, 0)]), Some((0, 1)));
+        assert_eq!(pair(&[(1500.0, 0), (1700.0, 5)]), Some((0, 1)));
+        // both allow 180 after 4 seconds, which isn't added up
+        assert_eq!(pair(&[(1500.0, 4), (1700.0, 4)]), None);
+    }
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "ssr")]
+
+//! Elo ratings for named players.
+
+/// Rating of a player who has never finished a rated game
+pub const INITIAL_RATING: f64 = 1500.0;
+/// Most a rating can change after one game
+const K_FACTOR: f64 = 32.0;
+
+/// Chance of the first player beating the second, according to their ratings.
+fn expected_score(rating: f64, opponent_rating: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent_rating - rating) / 400.0))
+}
+
+/// Both players' ratings after `winner` beat the other.
+pub fn updated_ratings(ratings: [f64; 2], winner: usize) -> [f64; 2] {
+    let [first, second] = ratings;
+    let expected = [expected_score(first, second), expected_score(second, first)];
+
+    std::array::from_fn(|id| {
+        let score = if id == winner { 1.0 } else { 0.0 };
+        ratings[id] + K_FACTOR * (score - expected[id])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_ratings_move_by_half_the_k_factor() {
+        for winner in 0..2 {
+            let new = updated_ratings([INITIAL_RATING; 2], winner);
+            assert_eq!(new[winner], INITIAL_RATING + 16.0);
+            assert_eq!(new[1 - winner], INITIAL_RATING - 16.0);
+        }
+    }
+
+    #[test]
+    fn ratings_change_by_opposite_amounts() {
+        for ratings in [[1500.0, 1500.0], [1200.0, 1800.0], [2100.0, 1350.5]] {
+            for winner in 0..2 {
+                let new = updated_ratings(ratings, winner);
+                let change = (new[0] - ratings[0]) + (new[1] - ratings[1]);
+                assert!(change.abs() < 1e-9, "{ratings:?} changed by {change}");
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Recordings of finished games.
+//!
+//! A replay holds everything needed to play a game back: both starting boards, the target,
+//! and every click in the order the server processed it. Clicks are applied with the same
+//! rules as in a live game, so boards are never stored after the start.
+//!
+//! Replays are versioned by their first field, `version`, which is bumped whenever the format
+//! changes. They come in two encodings:
+//!
+//! - bincode (the same encoding as the websocket protocol), which starts with the version as
+//!   a little endian `u32`
+//! - JSON, which looks like this:
+//!
+//! ```json
+//! {
+//!   "version": 2,
+//!   "game_id": 1234,
+//!   "started_at": 1700000000000,
+//!   "target": [["White", "Red", "Blue"], ["Green", "Green", "Yellow"], ["Orange", "Red", "White"]],
+//!   "boards": [
+//!     { "tiles": [["Blue", "Red", ...], ...], "hole": [2, 2] },
+//!     { "tiles": [...], "hole": [2, 2] }
+//!   ],
+//!   "clicks": [{ "player": 0, "pos": [2, 0], "time_ms": 1520 }, ...],
+//!   "winner": 0
+//! }
+//! ```
+//!
+//! Board tiles are `null` at the hole. `time_ms` counts from the start of the game, and
+//! `winner` is `null` if nobody won.
+//!
+//! Version 2 allows boards and targets of any size, so the bincode encoding stores the
+//! length of every row. Version 1 replays, which were always 5x5 with a 3x3 target, are
+//! still read, and come out as version 2.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::types::{BoardInner, Color, GameId, Target};
+
+pub const REPLAY_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub game_id: GameId,
+    /// Unix time in milliseconds
+    pub started_at: u64,
+    pub target: Target,
+    pub boards: [BoardInner; 2],
+    pub clicks: Vec<ReplayClick>,
+    pub winner: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ReplayClick {
+    pub player: usize,
+    pub pos: (usize, usize),
+    /// Time since the start of the game
+    pub time_ms: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("unsupported replay version {0}, expected at most {REPLAY_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("invalid replay: {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("invalid replay: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Only the version, which every format starts with.
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+impl Replay {
+    pub fn new(game_id: GameId, started_at: u64, target: Target, boards: [BoardInner; 2]) -> Self {
+        Replay {
+            version: REPLAY_VERSION,
+            game_id,
+            started_at,
+            target,
+            boards,
+            clicks: Vec::new(),
+            winner: None,
+        }
+    }
+
+    pub fn to_bincode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("failed to serialize")
+    }
+
+    pub fn from_bincode(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let version: u32 = bincode::deserialize(bytes)?;
+        match version {
+            1 => Ok(bincode::deserialize::<ReplayV1>(bytes)?.into()),
+            REPLAY_VERSION => Ok(bincode::deserialize(bytes)?),
+            _ => Err(ReplayError::UnsupportedVersion(version)),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("failed to serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ReplayError> {
+        let Version { version } = serde_json::from_str(json)?;
+        match version {
+            1 => Ok(serde_json::from_str::<ReplayV1>(json)?.into()),
+            REPLAY_VERSION => Ok(serde_json::from_str(json)?),
+            _ => Err(ReplayError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+/// The first format, from before boards could have other sizes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReplayV1 {
+    version: u32,
+    game_id: GameId,
+    started_at: u64,
+    target: [[Color; 3]; 3],
+    boards: [BoardV1; 2],
+    clicks: Vec<ReplayClick>,
+    winner: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BoardV1 {
+    tiles: [[Option<Color>; 5]; 5],
+    hole: (usize, usize),
+}
+
+impl From<ReplayV1> for Replay {
+    fn from(replay: ReplayV1) -> Self {
+        let board = |board: BoardV1| BoardInner {
+            tiles: board.tiles.map(Vec::from).into(),
+            hole: board.hole,
+        };
+        Replay {
+            version: REPLAY_VERSION,
+            game_id: replay.game_id,
+            started_at: replay.started_at,
+            target: replay.target.map(Vec::from).into(),
+            boards: replay.boards.map(board),
+            clicks: replay.clicks,
+            winner: replay.winner,
+        }
+    }
+}
+
+cfg_if::cfg_if! { if #[cfg(feature = "ssr")] {
+    use std::path::PathBuf;
+
+    /// Where replays are kept, set by `REPLAY_DIR`.
+    fn replay_dir() -> PathBuf {
+        std::env::var("REPLAY_DIR").unwrap_or_else(|_| "replays".to_string()).into()
+    }
+
+    fn replay_path(game_id: GameId) -> PathBuf {
+        replay_dir().join(format!("{game_id}.bin"))
+    }
+
+    pub(crate) async fn save(replay: Replay) {
+        let game_id = replay.game_id;
+        let result = tokio::task::spawn_blocking(move || {
+            std::fs::create_dir_all(replay_dir())?;
+            std::fs::write(replay_path(replay.game_id), replay.to_bincode())
+        })
+        .await
+        .expect("saving replay panicked");
+
+        if let Err(e) = result {
+            leptos::log!("Failed to save replay for game {game_id}: {e}");
+        }
+    }
+
+    pub(crate) async fn load(game_id: GameId) -> Option<Replay> {
+        let bytes = tokio::task::spawn_blocking(move || std::fs::read(replay_path(game_id)))
+            .await
+            .expect("loading replay panicked")
+            .ok()?;
+
+        match Replay::from_bincode(&bytes) {
+            Ok(replay) => Some(replay),
+            Err(e) => {
+                leptos::log!("Failed to load replay for game {game_id}: {e}");
+                None
+            }
+        }
+    }
+}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay() -> Replay {
+        let row = |colors: [Color; 3]| colors.to_vec();
+        let target = vec![
+            row([Color::White, Color::Red, Color::Blue]),
+            row([Color::Green, Color::Green, Color::Yellow]),
+            row([Color::Orange, Color::Red, Color::Pink]),
+        ];
+        let mut tiles = vec![vec![Some(Color::Purple); 5]; 5];
+        tiles[4][4] = None;
+        let board = BoardInner {
+            tiles,
+            hole: (4, 4),
+        };
+
+        let mut replay = Replay::new(1234, 1_700_000_000_000, target, [board.clone(), board]);
+        replay.clicks.push(ReplayClick {
+            player: 1,
+            pos: (4, 0),
+            time_ms: 1520,
+        });
+        replay.winner = Some(1);
+        replay
+    }
+
+    #[test]
+    fn bincode_round_trip() {
+        let replay = replay();
+        let decoded = Replay::from_bincode(&replay.to_bincode()).unwrap();
+        assert_eq!(decoded.to_json(), replay.to_json());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let replay = replay();
+        let decoded = Replay::from_json(&replay.to_json()).unwrap();
+        assert_eq!(decoded.to_json(), replay.to_json());
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let mut replay = replay();
+        replay.version = REPLAY_VERSION + 1;
+
+        let bincode = Replay::from_bincode(&replay.to_bincode());
+        assert!(
+            matches!(bincode, Err(ReplayError::UnsupportedVersion(v)) if v == REPLAY_VERSION + 1)
+        );
+        let json = Replay::from_json(&replay.to_json());
+        assert!(matches!(json, Err(ReplayError::UnsupportedVersion(v)) if v == REPLAY_VERSION + 1));
+    }
+
+    #[test]
+    fn version_1_is_upgraded() {
+        let mut tiles = [[Some(Color::Blue); 5]; 5];
+        tiles[2][2] = None;
+        let board = BoardV1 {
+            tiles,
+            hole: (2, 2),
+        };
+        let v1 = ReplayV1 {
+            version: 1,
+            game_id: 42,
+            started_at: 1_600_000_000_000,
+            target: [[Color::Red; 3]; 3],
+            boards: [board; 2],
+            clicks: Vec::new(),
+            winner: None,
+        };
+
+        let from_bincode = Replay::from_bincode(&bincode::serialize(&v1).unwrap()).unwrap();
+        let from_json = Replay::from_json(&serde_json::to_string(&v1).unwrap()).unwrap();
+        for replay in [from_bincode, from_json] {
+            assert_eq!(replay.version, REPLAY_VERSION);
+            assert_eq!(replay.game_id, 42);
+            assert_eq!(replay.target, vec![vec![Color::Red; 3]; 3]);
+            assert_eq!(replay.boards[1].tiles.len(), 5);
+            assert_eq!(replay.boards[1].tiles[2][2], None);
+            assert_eq!(replay.boards[1].hole, (2, 2));
+        }
+    }
+}
@@ -0,0 +1,471 @@
+//! Searches for short click sequences that make a board match its target.
+//!
+//! The search is weighted A*: with a weight of 1 the solution is as short as possible,
+//! and with a weight of `w` it is at most `w` times longer than the shortest one.
+//! Finding the shortest solution can take millions of nodes, while a weight around 6
+//! usually finds one within a couple of clicks of it in a few thousand.
+
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, BinaryHeap, HashMap},
+};
+
+use crate::types::{BoardInner, Color, Target, MAX_BOARD_SIZE};
+use strum::{EnumCount, IntoEnumIterator};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SolverOptions {
+    /// How much the heuristic is trusted over the path so far, trading length for speed
+    pub weight: u32,
+    /// Give up after expanding this many nodes
+    pub max_nodes: usize,
+}
+
+impl Default for SolverOptions {
+    fn default() -> Self {
+        SolverOptions {
+            weight: 1,
+            max_nodes: 1_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SolverStats {
+    pub nodes_expanded: usize,
+    pub nodes_generated: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution {
+    /// Positions to click, in order. Empty if the board already matches.
+    pub clicks: Vec<(usize, usize)>,
+    pub stats: SolverStats,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Unsolved {
+    pub stats: SolverStats,
+}
+
+/// A board packed into a number, with the hole's index in the lowest bits and then three bits
+/// for each tile's color. Searches keep millions of these, where full boards would take
+/// gigabytes.
+type PackedBoard = u128;
+
+const HOLE_BITS: u32 = 6;
+const COLOR_BITS: u32 = 3;
+
+const _: () = assert!(MAX_BOARD_SIZE * MAX_BOARD_SIZE <= 1 << HOLE_BITS);
+const _: () = assert!(Color::COUNT <= 1 << COLOR_BITS);
+const _: () = assert!(
+    HOLE_BITS as usize + MAX_BOARD_SIZE * MAX_BOARD_SIZE * COLOR_BITS as usize
+        <= PackedBoard::BITS as usize
+);
+
+fn pack(board: &BoardInner) -> PackedBoard {
+    let size = board.tiles.len();
+    let (row, col) = board.hole;
+    // the hole's own cell is left as zero
+    board.tiles.iter().flatten().rev().fold(0, |packed, tile| {
+        packed << COLOR_BITS | tile.map_or(0, |color| color as PackedBoard)
+    }) << HOLE_BITS
+        | (row * size + col) as PackedBoard
+}
+
+fn unpack(packed: PackedBoard, size: usize) -> BoardInner {
+    let hole = packed_hole(packed, size);
+    let mut colors = packed >> HOLE_BITS;
+    let tiles = (0..size)
+        .map(|i| {
+            (0..size)
+                .map(|j| {
+                    let color = Color::from(colors as usize & ((1 << COLOR_BITS) - 1));
+                    colors >>= COLOR_BITS;
+                    ((i, j) != hole).then_some(color)
+                })
+                .collect()
+        })
+        .collect();
+    BoardInner { tiles, hole }
+}
+
+fn packed_hole(packed: PackedBoard, size: usize) -> (usize, usize) {
+    let hole = packed as usize & ((1 << HOLE_BITS) - 1);
+    (hole / size, hole % size)
+}
+
+/// The last click of a node is where its hole is, so only the way back is kept.
+struct Node {
+    board: PackedBoard,
+    parent: Option<usize>,
+    moves: u32,
+}
+
+/// Finds a sequence of clicks that makes `board` match `target`.
+///
+/// Fails if the search runs out of nodes, or if the board can't reach the target at all.
+pub fn solve(
+    board: &BoardInner,
+    target: &Target,
+    options: &SolverOptions,
+) -> Result<Solution, Unsolved> {
+    let weight = options.weight.max(1);
+    // the heuristic counts in fractions of a click
+    let scale = board.tiles.len() as u32 - 1;
+    let mut stats = SolverStats::default();
+
+    if !has_tiles_for(board, target) {
+        return Err(Unsolved { stats });
+    }
+
+    let size = board.tiles.len();
+    let mut nodes = vec![Node {
+        board: pack(board),
+        parent: None,
+        moves: 0,
+    }];
+    let mut best_moves: HashMap<PackedBoard, u32> = HashMap::from([(nodes[0].board, 0)]);
+    // ties are broken towards the node closer to the target
+    let h = heuristic(board, target);
+    let mut open = BinaryHeap::from([(Reverse(weight.saturating_mul(h)), Reverse(h), 0)]);
+
+    while let Some((_, _, idx)) = open.pop() {
+        let Node { board, moves, .. } = nodes[idx];
+
+        // skip nodes that were reached by a shorter path after being queued
+        if best_moves[&board] < moves {
+            continue;
+        }
+        let board = unpack(board, size);
+        if board.matches_target(target) {
+            return Ok(Solution {
+                clicks: path(&nodes, idx, size),
+                stats,
+            });
+        }
+        if stats.nodes_expanded == options.max_nodes {
+            break;
+        }
+        stats.nodes_expanded += 1;
+
+        for click in valid_clicks(&board) {
+            let mut next = board.clone();
+            next.click(click);
+            stats.nodes_generated += 1;
+
+            let packed = pack(&next);
+            match best_moves.entry(packed) {
+                Entry::Occupied(entry) if *entry.get() <= moves + 1 => continue,
+                Entry::Occupied(mut entry) => _ = entry.insert(moves + 1),
+                Entry::Vacant(entry) => _ = entry.insert(moves + 1),
+            }
+
+            let h = heuristic(&next, target);
+            open.push((
+                Reverse(((moves + 1) * scale).saturating_add(weight.saturating_mul(h))),
+                Reverse(h),
+                nodes.len(),
+            ));
+            nodes.push(Node {
+                board: packed,
+                parent: Some(idx),
+                moves: moves + 1,
+            });
+        }
+    }
+
+    Err(Unsolved { stats })
+}
+
+/// Whether the board has at least as many tiles of each color as the target.
+fn has_tiles_for(board: &BoardInner, target: &Target) -> bool {
+    Color::iter().all(|color| {
+        let needed = target.iter().flatten().filter(|&&c| c == color).count();
+        let available = board
+            .tiles
+            .iter()
+            .flatten()
+            .filter(|&&tile| tile == Some(color))
+            .count();
+        needed <= available
+    })
+}
+
+/// Every position in line with the hole, which are the only ones that move tiles.
+pub fn valid_clicks(board: &BoardInner) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let (row, col) = board.hole;
+    let size = board.tiles.len();
+
+    (0..size)
+        .filter(move |&i| i != row)
+        .map(move |i| (i, col))
+        .chain((0..size).filter(move |&j| j != col).map(move |j| (row, j)))
+}
+
+fn path(nodes: &[Node], mut idx: usize, size: usize) -> Vec<(usize, usize)> {
+    let mut clicks = Vec::new();
+    while let Some(parent) = nodes[idx].parent {
+        clicks.push(packed_hole(nodes[idx].board, size));
+        idx = parent;
+    }
+    clicks.reverse();
+    clicks
+}
+
+/// A lower bound on the number of clicks left, in units of `1 / (size - 1)` clicks.
+///
+/// A click moves every tile in its line by one step, so it moves at most `size - 1` tiles
+/// and each of them only by one. The target cells of each color are matched up with the
+/// nearest tiles of that color, and the total and longest distance bound the clicks needed.
+/// A click also changes at most one row or column of the target area, which bounds how many
+/// mismatches it can fix.
+///
+/// Returns `u32::MAX` if the board doesn't have enough tiles of some color for the target.
+pub fn heuristic(board: &BoardInner, target: &Target) -> u32 {
+    let size = board.tiles.len();
+    let target_size = target.len();
+    let offset = (size - target_size) / 2;
+    let max_moved = (size - 1) as u32;
+
+    let mut mismatches = 0;
+    let mut total_distance = 0;
+    let mut max_distance = 0;
+
+    for color in Color::iter() {
+        let cells: Vec<_> = target
+            .iter()
+            .enumerate()
+            .flat_map(|(i, row)| row.iter().enumerate().map(move |(j, &c)| (i, j, c)))
+            .filter(|&(_, _, c)| c == color)
+            .map(|(i, j, _)| (i + offset, j + offset))
+            .filter(|&(i, j)| board.tiles[i][j] != Some(color))
+            .collect();
+        if cells.is_empty() {
+            continue;
+        }
+
+        let tiles: Vec<_> = board
+            .tiles
+            .iter()
+            .enumerate()
+            .flat_map(|(i, row)| row.iter().enumerate().map(move |(j, &t)| (i, j, t)))
+            .filter(|&(_, _, t)| t == Some(color))
+            .map(|(i, j, _)| (i, j))
+            .collect();
+
+        let Some((total, max)) = assign(&cells, &tiles) else {
+            return u32::MAX;
+        };
+        mismatches += cells.len() as u32;
+        total_distance += total;
+        max_distance = max_distance.max(max);
+    }
+
+    // everything is scaled by max_moved so the total distance doesn't need rounding
+    total_distance
+        .max(max_distance * max_moved)
+        .max((mismatches * max_moved).div_ceil(target_size as u32))
+}
+
+/// The least total distance, and the least longest distance, of moving distinct tiles onto
+/// the cells. These can come from different assignments, and both bound the clicks needed.
+///
+/// Cells are assigned in order, so the best ways to cover the first cells only depend on
+/// which tiles they used. A color has at most
+/// [`MAX_TILES_PER_COLOR`](crate::types::MAX_TILES_PER_COLOR) tiles, which keeps the sets of
+/// used tiles few. Returns `None` if there are fewer tiles than cells.
+fn assign(cells: &[(usize, usize)], tiles: &[(usize, usize)]) -> Option<(u32, u32)> {
+    fn distance(a: (usize, usize), b: (usize, usize)) -> u32 {
+        (a.0.abs_diff(b.0) + a.1.abs_diff(b.1)) as u32
+    }
+    fn better(a: Option<(u32, u32)>, b: (u32, u32)) -> (u32, u32) {
+        a.map_or(b, |a| (a.0.min(b.0), a.1.min(b.1)))
+    }
+
+    if cells.len() > tiles.len() {
+        return None;
+    }
+
+    // indexed by the set of used tiles, covering as many cells as there are tiles in it
+    let mut best: Vec<Option<(u32, u32)>> = vec![None; 1 << tiles.len()];
+    best[0] = Some((0, 0));
+    let mut result = None;
+
+    for used in 0..best.len() {
+        let Some((total, max)) = best[used] else {
+            continue;
+        };
+        let Some(&cell) = cells.get(used.count_ones() as usize) else {
+            result = Some(better(result, (total, max)));
+            continue;
+        };
+
+        for (k, &tile) in tiles.iter().enumerate() {
+            if used & (1 << k) != 0 {
+                continue;
+            }
+            let d = distance(cell, tile);
+            let next = used | (1 << k);
+            best[next] = Some(better(best[next], (total + d, max.max(d))));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+
+    use super::*;
+    use crate::types::BoardTiles;
+
+    /// Reads rows of colors by their first letter, with `.` at the hole.
+    fn board(rows: &[&str]) -> BoardInner {
+        let tiles: BoardTiles = rows
+            .iter()
+            .map(|row| row.chars().map(|c| (c != '.').then(|| color(c))).collect())
+            .collect();
+        let hole = rows
+            .iter()
+            .enumerate()
+            .find_map(|(i, row)| row.find('.').map(|j| (i, j)))
+            .expect("board has a hole");
+        BoardInner { tiles, hole }
+    }
+
+    fn target(rows: &[&str]) -> Target {
+        rows.iter()
+            .map(|row| row.chars().map(color).collect())
+            .collect()
+    }
+
+    fn color(c: char) -> Color {
+        Color::iter()
+            .find(|color| format!("{color:?}").starts_with(c))
+            .expect("known color")
+    }
+
+    fn clicked(board: &BoardInner, clicks: &[(usize, usize)]) -> BoardInner {
+        let mut board = board.clone();
+        for &click in clicks {
+            assert!(board.click(click), "{click:?} moves no tile");
+        }
+        board
+    }
+
+    fn matched() -> (BoardInner, Target) {
+        let board = board(&["WYYWB", "BRGBW", "YGRGY", "WBGRB", "RWY.O"]);
+        let target = target(&["RGB", "GRG", "BGR"]);
+        assert!(board.matches_target(&target));
+        (board, target)
+    }
+
+    #[test]
+    fn matching_board_needs_no_clicks() {
+        let (board, target) = matched();
+        let solution = solve(&board, &target, &SolverOptions::default()).unwrap();
+        assert!(solution.clicks.is_empty());
+    }
+
+    #[test]
+    fn packed_boards_unpack_to_the_same_board() {
+        let large = board(&["PBPBPB", "BPBPBP", "P.PBPB", "BPBPBP", "PBPBPB", "BPBPBP"]);
+        for board in [matched().0, large] {
+            let unpacked = unpack(pack(&board), board.tiles.len());
+            assert_eq!(unpacked.tiles, board.tiles);
+            assert_eq!(unpacked.hole, board.hole);
+        }
+    }
+
+    #[test]
+    fn finds_shortest_solution() {
+        let (board, target) = matched();
+
+        let one_away = clicked(&board, &[(1, 3)]);
+        let solution = solve(&one_away, &target, &SolverOptions::default()).unwrap();
+        assert_eq!(solution.clicks, [(4, 3)]);
+
+        let two_away = clicked(&one_away, &[(1, 0)]);
+        assert!(valid_clicks(&two_away)
+            .all(|click| !clicked(&two_away, &[click]).matches_target(&target)));
+        let solution = solve(&two_away, &target, &SolverOptions::default()).unwrap();
+        assert_eq!(solution.clicks.len(), 2);
+        assert!(clicked(&two_away, &solution.clicks).matches_target(&target));
+    }
+
+    /// Clicks needed from every board that can be reached from `start`, found by searching
+    /// back from the boards that match, since every click can be undone by another.
+    fn distances(start: &BoardInner, target: &Target) -> Vec<(BoardInner, u32)> {
+        let mut boards = HashMap::from([(start.tiles.clone(), start.clone())]);
+        let mut queue = VecDeque::from([start.clone()]);
+        while let Some(board) = queue.pop_front() {
+            for click in valid_clicks(&board) {
+                let next = clicked(&board, &[click]);
+                if !boards.contains_key(&next.tiles) {
+                    boards.insert(next.tiles.clone(), next.clone());
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        let mut distances: HashMap<BoardTiles, u32> = HashMap::new();
+        let mut queue = VecDeque::new();
+        for board in boards.values().filter(|board| board.matches_target(target)) {
+            distances.insert(board.tiles.clone(), 0);
+            queue.push_back(board.clone());
+        }
+        while let Some(board) = queue.pop_front() {
+            let distance = distances[&board.tiles];
+            for click in valid_clicks(&board) {
+                let next = clicked(&board, &[click]);
+                if !distances.contains_key(&next.tiles) {
+                    distances.insert(next.tiles.clone(), distance + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        assert_eq!(distances.len(), boards.len());
+        boards
+            .into_values()
+            .map(|board| {
+                let distance = distances[&board.tiles];
+                (board, distance)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn heuristic_never_overestimates() {
+        let cases = [
+            (board(&["RRG", "GGB", "BR."]), target(&["G"])),
+            (
+                board(&["RRRR", "RGRR", "RRGR", "RRG."]),
+                target(&["RG", "GR"]),
+            ),
+        ];
+
+        for (start, target) in cases {
+            let scale = start.tiles.len() as u32 - 1;
+            for (board, distance) in distances(&start, &target) {
+                let h = heuristic(&board, &target);
+                assert!(
+                    h <= distance * scale,
+                    "heuristic {h} over {distance} clicks for {:?}",
+                    board.tiles
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unreachable_target_fails_right_away() {
+        let board = board(&["WWWWW", "WWWWW", "WWRWW", "WWWWW", "WWWW."]);
+        let target = target(&["RRR", "RRR", "RRR"]);
+
+        assert_eq!(heuristic(&board, &target), u32::MAX);
+        let unsolved = solve(&board, &target, &SolverOptions::default()).unwrap_err();
+        assert_eq!(unsolved.stats.nodes_expanded, 0);
+    }
+}
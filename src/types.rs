@@ -1,31 +1,236 @@
+use std::{fmt, str::FromStr};
+
 use serde::{Deserialize, Serialize};
-use strum::{EnumCount, EnumIter};
+use strum::{EnumCount, EnumIter, IntoEnumIterator};
+use thiserror::Error;
+
+pub type GameId = u64;
+
+/// Version of [`ClientMessage`] and [`ServerMessage`], raised whenever either changes.
+///
+/// Their JSON encoding is described by `public/protocol.schema.json`, which has to follow.
+pub const PROTOCOL_VERSION: u32 = 3;
+/// Oldest client version the server still talks to
+pub const MIN_PROTOCOL_VERSION: u32 = 3;
+
+/// The first frame each side of a connection sends, before any message.
+///
+/// Its layout never changes, so both sides can read it whatever their version. The server
+/// answers with the client's version and the capabilities both sides have if it can talk to the
+/// client. Otherwise it answers with its own version and closes the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub version: u32,
+    pub capabilities: Capabilities,
+}
+
+impl Hello {
+    pub fn current(capabilities: Capabilities) -> Self {
+        Hello {
+            version: PROTOCOL_VERSION,
+            capabilities,
+        }
+    }
+}
+
+/// Optional parts of the protocol, as bit flags, that a client may not handle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const NONE: Self = Capabilities(0);
+    /// [`ServerMessage::Clock`] during a round
+    pub const CLOCK: Self = Capabilities(1);
+    /// [`ServerMessage::MoveRejected`], without it rejected clicks are answered with a `Resync`
+    pub const MOVE_REJECTED: Self = Capabilities(1 << 1);
+    pub const ALL: Self = Capabilities(Self::CLOCK.0 | Self::MOVE_REJECTED.0);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The capabilities in both.
+    pub fn intersection(self, other: Self) -> Self {
+        Capabilities(self.0 & other.0)
+    }
+}
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum ServerMessage {
     GameStart(GameStart),
     OpponentLeft,
-    OpponentClick { pos: (usize, usize) },
-    GameEnd { is_win: bool },
+    OpponentClick {
+        pos: (usize, usize),
+        /// Time since the start of the round
+        time_ms: u64,
+    },
+    /// The end of a round, the series goes on unless someone has won a majority
+    GameEnd {
+        outcome: Outcome,
+        /// The time limit ran out, and the player closest to the target won
+        time_up: bool,
+        /// Set when both players have names and someone won
+        rating: Option<RatingChange>,
+        series: Series,
+    },
+    RoomCreated {
+        code: String,
+    },
+    RoomNotFound,
+    OpponentDisconnected,
+    OpponentReconnected,
+    /// The full game state, sent after reconnecting and followed by the `GameEnd` if the round
+    /// is over. Also answers rejected clicks not answered by `MoveRejected`
+    Resync(GameStart),
+    /// The session could not be resumed, usually because the game is over
+    SessionExpired,
+    SpectateStart(SpectateStart),
+    PlayerClick {
+        player: usize,
+        pos: (usize, usize),
+        /// Time since the start of the round
+        time_ms: u64,
+    },
+    /// Sent to spectators instead of `GameEnd` or `OpponentLeft`
+    GameOver {
+        winner: Option<usize>,
+        series: Series,
+    },
+    GameNotFound,
+    PracticeStart {
+        target: Target,
+        board: BoardInner,
+    },
+    /// The practice board matches the target
+    PracticeEnd {
+        moves: u32,
+        time_ms: u64,
+    },
+    /// The opponent wants to play another series
+    RematchRequested,
+    /// Keeps the client's clock in step with the server's
+    Clock {
+        /// Time since the start of the round
+        elapsed_ms: u64,
+    },
+    /// A click was not applied, and `board` is the player's board as the server has it
+    MoveRejected {
+        pos: (usize, usize),
+        reason: MoveError,
+        board: BoardInner,
+        /// How many more rejected clicks forfeit the round, if any do
+        strikes_left: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Error)]
+pub enum MoveError {
+    #[error("the position is off the board")]
+    OutOfBounds,
+    /// The position is the hole, or not in line with it
+    #[error("no tile can slide from there")]
+    NoTileMoved,
+}
+
+/// Rows of colors, as many as there are columns, that the middle of the board has to match.
+pub type Target = Vec<Vec<Color>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    Win,
+    Loss,
+    /// Both players were as close to the target when time ran out
+    Draw,
 }
 
-pub type Target = [[Color; 3]; 3];
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RatingChange {
+    /// Rating after the game
+    pub rating: i32,
+    pub delta: i32,
+}
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GameStart {
+    pub game_id: GameId,
     pub target: Target,
     pub board: BoardInner,
     pub opponent_board: BoardInner,
+    /// Token for resuming the game after losing connection
+    pub session: String,
+    pub opponent_name: Option<String>,
+    /// The opponent is a program rather than a person
+    pub opponent_is_bot: bool,
+    pub series: Series,
+    /// Moves each player has made this round, own first
+    pub moves: [u32; 2],
+    /// Time since the start of the round, which is more than zero after reconnecting
+    pub elapsed_ms: u64,
+    /// The round ends after this long, with the player closest to the target winning
+    pub time_limit_ms: Option<u64>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SpectateStart {
+    pub game_id: GameId,
+    pub target: Target,
+    pub boards: [BoardInner; 2],
+    pub names: [Option<String>; 2],
+    pub series: Series,
+}
+
+/// Score of a best-of-N series, a single game is a series of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Series {
+    pub best_of: u32,
+    /// Rounds won by each player, messages to a player list their own wins first
+    pub wins: [u32; 2],
+}
+
+impl Series {
+    pub fn new(best_of: u32) -> Self {
+        Series {
+            best_of,
+            wins: [0, 0],
+        }
+    }
+
+    /// The player who has won a majority of the rounds, if any.
+    pub fn winner(&self) -> Option<usize> {
+        (0..2).find(|&id| self.wins[id] > self.best_of / 2)
+    }
+
+    /// The score as seen by player `id`.
+    pub fn for_player(self, id: usize) -> Self {
+        let [first, second] = self.wins;
+        let wins = if id == 0 {
+            [first, second]
+        } else {
+            [second, first]
+        };
+        Series { wins, ..self }
+    }
+}
+
+/// Display names are trimmed and cut to this many characters by the server.
+pub const MAX_NAME_LEN: usize = 20;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub enum ClientMessage {
-    Click { pos: (usize, usize) },
+    /// The first message on every connection, the server waits for it before anything else
+    Join {
+        name: Option<String>,
+    },
+    Click {
+        pos: (usize, usize),
+    },
     Ping,
+    /// Asks for, or accepts, another series once the current one is over
+    Rematch,
 }
 
 #[derive(
-    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter, EnumCount,
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, EnumIter, EnumCount,
 )]
 pub enum Color {
     #[default]
@@ -35,6 +240,8 @@ pub enum Color {
     Red,
     Green,
     Blue,
+    Purple,
+    Pink,
 }
 
 impl From<usize> for Color {
@@ -46,34 +253,199 @@ impl From<usize> for Color {
             3 => Color::Red,
             4 => Color::Green,
             5 => Color::Blue,
+            6 => Color::Purple,
+            7 => Color::Pink,
             _ => panic!("out of bounds"),
         }
     }
 }
 
-pub type BoardTiles<T = Color> = [[Option<T>; 5]; 5];
+/// Rows of tiles, as many as there are columns, with `None` at the hole.
+pub type BoardTiles<T = Color> = Vec<Vec<Option<T>>>;
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BoardInner<T = Color> {
     pub tiles: BoardTiles<T>,
     pub hole: (usize, usize),
 }
 
+/// Side lengths of the board and of the target, which lines up with the middle of the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoardSize {
+    pub board: usize,
+    pub target: usize,
+}
+
+/// Largest board that can be picked
+pub const MAX_BOARD_SIZE: usize = 6;
+
+impl BoardSize {
+    pub const SMALL: Self = BoardSize {
+        board: 4,
+        target: 2,
+    };
+    pub const NORMAL: Self = BoardSize {
+        board: 5,
+        target: 3,
+    };
+    pub const LARGE: Self = BoardSize {
+        board: 6,
+        target: 4,
+    };
+
+    /// Whether the target fits in the board with the same margin on every side.
+    pub fn is_valid(&self) -> bool {
+        (3..=MAX_BOARD_SIZE).contains(&self.board)
+            && (1..self.board).contains(&self.target)
+            && (self.board - self.target) % 2 == 0
+    }
+
+    /// Rows and columns between the edge of the board and the target.
+    pub fn margin(&self) -> usize {
+        (self.board - self.target) / 2
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.board * self.board - 1
+    }
+}
+
+impl Default for BoardSize {
+    fn default() -> Self {
+        BoardSize::NORMAL
+    }
+}
+
+/// Written as `5x3`, the side of the board and then that of the target.
+impl fmt::Display for BoardSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.board, self.target)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidBoardSize;
+
+impl FromStr for BoardSize {
+    type Err = InvalidBoardSize;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (board, target) = s.split_once('x').ok_or(InvalidBoardSize)?;
+        let size = BoardSize {
+            board: board.parse().map_err(|_| InvalidBoardSize)?,
+            target: target.parse().map_err(|_| InvalidBoardSize)?,
+        };
+        size.is_valid().then_some(size).ok_or(InvalidBoardSize)
+    }
+}
+
+/// Fewest colors a board can have
+pub const MIN_COLORS: usize = 2;
+/// Colors of a board when none are picked
+pub const DEFAULT_COLORS: usize = 6;
+/// Most tiles of one color, which keeps the solver's heuristic quick
+pub const MAX_TILES_PER_COLOR: usize = 8;
+
+/// How many tiles of each color a board has, indexed by [`Color`].
+///
+/// The target is drawn from the same tiles, so it never needs more of a color than there is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette([usize; Color::COUNT]);
+
+impl Palette {
+    /// The first `colors` colors, sharing the tiles of a board of `size` as evenly as possible.
+    pub fn even(colors: usize, size: BoardSize) -> Option<Self> {
+        if !(MIN_COLORS..=Color::COUNT).contains(&colors) {
+            return None;
+        }
+
+        let tiles = size.tile_count();
+        let mut counts = [0; Color::COUNT];
+        for (i, count) in counts[..colors].iter_mut().enumerate() {
+            *count = tiles / colors + usize::from(i < tiles % colors);
+        }
+        Palette::custom(&counts, size)
+    }
+
+    /// Tiles of each color, in the order of [`Color`], if they fill a board of `size`.
+    pub fn custom(counts: &[usize], size: BoardSize) -> Option<Self> {
+        let colors = counts.iter().filter(|&&count| count > 0).count();
+        if counts.len() > Color::COUNT
+            || colors < MIN_COLORS
+            || counts.iter().any(|&count| count > MAX_TILES_PER_COLOR)
+            || counts.iter().sum::<usize>() != size.tile_count()
+        {
+            return None;
+        }
+
+        let mut palette = [0; Color::COUNT];
+        palette[..counts.len()].copy_from_slice(counts);
+        Some(Palette(palette))
+    }
+
+    /// The palette of a board whose colors weren't picked.
+    pub fn standard(size: BoardSize) -> Self {
+        Palette::even(DEFAULT_COLORS, size).expect("the default colors fit every board size")
+    }
+
+    pub fn count(&self, color: Color) -> usize {
+        self.0[color as usize]
+    }
+
+    /// Every tile of a board, grouped by color.
+    pub fn tile_colors(&self) -> Vec<Color> {
+        Color::iter()
+            .flat_map(|color| std::iter::repeat(color).take(self.count(color)))
+            .collect()
+    }
+}
+
+/// Parses tile counts written as `4,4,4,3`, without checking that they make a palette.
+pub fn parse_tile_counts(counts: &str) -> Option<Vec<usize>> {
+    counts
+        .split(',')
+        .map(|count| count.trim().parse().ok())
+        .collect()
+}
+
+impl<T: Copy> BoardInner<T> {
+    /// Slides the tiles between `pos` and the hole, returning whether anything moved.
+    pub fn click(&mut self, pos: (usize, usize)) -> bool {
+        use crate::utils::slide;
+
+        let BoardInner { tiles, hole } = self;
+        let update = |old: (usize, usize), new: (usize, usize)| {
+            tiles[new.0][new.1] = tiles[old.0][old.1];
+        };
+
+        if !slide(pos, *hole, update) {
+            return false;
+        }
+
+        tiles[pos.0][pos.1] = None;
+        *hole = pos;
+        true
+    }
+}
+
 impl<T> BoardInner<T>
 where
     T: Into<Color> + Copy,
 {
     pub fn matches_target(&self, target: &Target) -> bool {
-        for (board_row, target_row) in self.tiles[1..=3].iter().zip(target) {
-            for (tile, target_color) in board_row[1..=3].iter().zip(target_row) {
-                if !tile
-                    .map(|tile| tile.into() == *target_color)
-                    .unwrap_or(false)
-                {
-                    return false;
-                }
-            }
-        }
-        true
+        self.matching_tiles(target) == target.len() * target.len()
+    }
+
+    /// How many tiles in the middle of the board have the color the target has there.
+    pub fn matching_tiles(&self, target: &Target) -> usize {
+        let margin = (self.tiles.len() - target.len()) / 2;
+        let inner = margin..margin + target.len();
+
+        self.tiles[inner.clone()]
+            .iter()
+            .zip(target)
+            .flat_map(|(board_row, target_row)| board_row[inner.clone()].iter().zip(target_row))
+            .filter(|(tile, target_color)| tile.is_some_and(|tile| tile.into() == **target_color))
+            .count()
     }
 }